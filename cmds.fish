@@ -21,7 +21,14 @@ function deploy --description "for the first time deploy of a component with its
 end
 
 # for updates use this
-function redeploy -a version --description "redeploy to a version"
+# auto replays the worker's oplog against the new code, which only works if
+# the new code does exactly what the old code did. manual has golem take a
+# snapshot of the stores with the old code, and load it into the new code, so
+# use that when anything has changed in how the stores are kept.
+function redeploy -a mode --description "redeploy to the latest version, with update mode auto (the default) or manual"
+  if test -z "$mode"
+    set mode auto
+  end
   cargo component build --release || return 1
 
   # Use `golem-cli component update` to figure out which version to use.
@@ -41,7 +48,7 @@ function redeploy -a version --description "redeploy to a version"
 
   # do the update
   echo -n "Updating to component version $target_version... "
-  golem-cli worker update --worker-name fst --target-version $target_version --mode auto --component-name slkvs
+  golem-cli worker update --worker-name fst --target-version $target_version --mode $mode --component-name slkvs
 end
 
 function worker_restart
@@ -51,6 +58,84 @@ end
 
 ################################
 # talking to the KV store
+
+# every function below works on this store, which has to be made with
+# create_store first. `set slkvs_store other` to switch.
+set -q slkvs_store; or set -g slkvs_store default
+# and as this principal, who gets admin on stores they create
+set -q slkvs_principal; or set -g slkvs_principal $USER
+
+function create_store -a name --description "Create a new empty store"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/createstore \
+    --parameters=(gli_parameters $slkvs_principal $name)
+end
+
+function list_stores
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/liststores \
+    --parameters=(gli_parameters $slkvs_principal)
+end
+
+function export_store -a name --description "Dump everything in a store as json"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/exportstore \
+    --parameters=(gli_parameters $slkvs_principal $name)
+end
+
+function import_store -a name dump_file --description "Make a new store from a dump file"
+  set escaped_dump (cat $dump_file | tr -d "\n" | jq . -Rs | tr -d "\n")
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/importstore \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $name) $escaped_dump)
+end
+
+function grant -a grantee prefix permission --description "Give grantee read write or admin on prefix in the current store"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/grant \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store $grantee $prefix) $permission)
+end
+
+function revoke -a grantee prefix --description "Take away grantee's permission on prefix in the current store"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/revoke \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $grantee $prefix)
+end
+
+function listacl --description "Grants in the current store"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/listacl \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store)
+end
+
+function deliver_hooks --description "Post what's queued for the current store's hooks, and say how many are still waiting"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/deliverhooks \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store)
+end
+
+function hook_failures --description "Hooks in the current store whose last post failed, and why"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/hookfailures \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store)
+end
+
+function drop_store -a name --description "Remove a store and everything in it"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/dropstore \
+    --parameters=(gli_parameters $slkvs_principal $name)
+end
+
 function gli_quote
   for v in $argv
     echo -n "$v" | jq -Rs
@@ -72,47 +157,225 @@ end
 function get
   golem-cli worker invoke-and-await --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/get \
-    --parameters=(gli_parameters $argv[1])
+    --function=golem:component/data/get \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
 end
 
-function hgettree --description "For a given path, retrieve the entire subtree, with output in json"
+function hgettree -a path maxdepth --description "For a given path, retrieve the entire subtree, with output in json. Optionally only to maxdepth."
   set component_id (gli_component_id)
   set worker_name fst
-  set function_name golem:component/api/gettree
+  set function_name golem:component/data/gettree
 
-  set params "{\"params\": $(gli_parameters $argv[1])}"
+  # option<u32> is either null or the number
+  if test -z "$maxdepth"
+    set maxdepth null
+  end
+  set params "{\"params\": $(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store $path) $maxdepth)}"
   set url "http://localhost:9881/v2/components/$component_id/workers/$worker_name/invoke-and-await?function=$function_name&calling-convention=Component"
-  echo -e (curl --silent --json $params $url) | jq .result[0] | string unescape | jq .
+  set rsp (curl --silent --json $params $url)
+  echo -e $rsp | jq .result[0].json | string unescape | jq .
+  # where maxdepth cut it off, which is null in the json
+  echo -e $rsp | jq -c .result[0].truncated
 end
 
-function gettree --description "For a given path, retrieve the entire subtree, with output in WAVE"
+function gettree -a path maxdepth --description "For a given path, retrieve the entire subtree, with output in WAVE. Optionally only to maxdepth."
+  if test -n "$maxdepth"
+    set maxdepth "some($maxdepth)"
+  else
+    set maxdepth none
+  end
+
   golem-cli worker invoke-and-await --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/gettree \
-    --parameters=(gli_parameters $argv[1])
+    --function=golem:component/data/gettree \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) (gli_quote $path) $maxdepth)
 end
 
 function delete  --description "For a given path, delete the value. Fails on a subtree."
   golem-cli worker invoke-and-await --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/delete \
-    --parameters=(gli_parameters $argv[1])
+    --function=golem:component/data/delete \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
+end
+
+function deletetree -a path --description "Delete path and everything below it"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/deletetree \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $path)
+end
+
+function undo -a n --description "Take back the last n changes, or 1"
+  test -n "$n"; or set n 1
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/undo \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) $n)
 end
 
 function add --description "For a given path, add the value."
   golem-cli worker invoke-and-await --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/add \
-    --parameters=(gli_parameters $argv[1] $argv[2])
+    --function=golem:component/data/add \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1] $argv[2])
+end
+
+function getvalue --description "For a given path, get the value with its type"
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/getvalue \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
+end
+
+function setvalue -a path kind value --description "For a given path, set a value of kind str num boolean or null"
+  switch $kind
+    case str num
+      set leaf "$kind($(gli_quote $value))"
+    case boolean
+      set leaf "boolean($value)"
+    case '*'
+      set leaf $kind
+  end
+
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/setvalue \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) (gli_quote $path) $leaf)
+end
+
+function history -a path limit --description "The last changes to path, newest first, with times in ms since the epoch"
+  if test -z "$limit"
+    set limit 10
+  end
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/history \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store $path) $limit)
+end
+
+function getat -a path when --description "What path had at a time, anything date -d understands eg 'last tuesday'"
+  set at (math (date -d "$when" +%s) \* 1000)
+  golem-cli worker invoke-and-await --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/getat \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store $path) $at)
 end
 
 function listpaths
   golem-cli worker invoke-and-await \
     --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/listpaths \
-    --parameters=(gli_parameters $argv[1])
+    --function=golem:component/query/listpaths \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
+end
+
+function listentries --description "List all paths under prefix, with their values"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/listentries \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
+end
+
+function exists --description "Is there a value at the path, or anything below it"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/data/exists \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
+end
+
+function count --description "How many values are at or below the prefix"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/count \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $argv[1])
+end
+
+function changes -a since prefix limit --description "Up to limit changes under prefix after revision since, default 0 and 100"
+  test -n "$since"; or set since 0
+  test -n "$limit"; or set limit 100
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/changes \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) $since (gli_quote $prefix) $limit)
+end
+
+function watch -a since prefix limit --description "Same as changes, except if there are none yet it returns a promise to await, and the since to call it with then"
+  test -n "$since"; or set since 0
+  test -n "$limit"; or set limit 100
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/watch \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) $since (gli_quote $prefix) $limit)
+end
+
+function diff -a a b --description "Leaves that differ between the subtrees at a and b, eg staging/app prod/app"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/diff \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $a $b)
+end
+
+function diffpatch -a a b --description "Same as diff, as an RFC 6902 json patch that turns a into b"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/diffpatch \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $a $b)
+end
+
+function find -a kind value prefix --description "Paths whose value matches. kind is one of exact prefix substring regex. Optionally under prefix."
+  if test -n "$prefix"
+    set prefix "some($(gli_quote $prefix))"
+  else
+    set prefix none
+  end
+
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/find \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) "$kind($(gli_quote $value))" $prefix)
+end
+
+function add_schema -a name prefix schema --description "Make everything at prefix match a json schema, from a file or the command line"
+  if test -f $schema
+    set schema (cat $schema | string collect)
+  end
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/addschema \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $name $prefix $schema)
+end
+
+function drop_schema -a name --description "Stop checking writes against the named schema"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/dropschema \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $name)
+end
+
+function addindex -a name pattern --description "Index the values at paths matching pattern, eg users/*/email"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/admin/addindex \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $name $pattern)
+end
+
+function lookup -a name value --description "Parent paths of the values in the named index that are equal to value"
+  golem-cli worker invoke-and-await \
+    --component-name=slkvs \
+    --worker-name=fst \
+    --function=golem:component/query/lookup \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store $name $value)
 end
 
 function gli_component_id
@@ -130,8 +393,8 @@ end
 function hlistpaths --description "List all paths, with output in json rather than WAVE"
   set component_id (gli_component_id)
   set worker_name fst
-  set function_name golem:component/api/listpaths
-  set json_rsp (curl --silent --json '{"params": []}' "http://localhost:9881/v2/components/$component_id/workers/$worker_name/invoke-and-await?function=$function_name&calling-convention=Component")
+  set function_name golem:component/query/listpaths
+  set json_rsp (curl --silent --json "{\"params\": $(gli_parameters $slkvs_principal $slkvs_store)}" "http://localhost:9881/v2/components/$component_id/workers/$worker_name/invoke-and-await?function=$function_name&calling-convention=Component")
   # because golem api returns this in a top-level result: []
   echo $json_rsp | jq .result[0]
 end
@@ -152,13 +415,32 @@ function addtree
   golem-cli worker invoke-and-await \
     --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/addtree \
-    --parameters=(gli_noquote_parameters (gli_quote $argv[1]) $escaped_tree)
+    --function=golem:component/data/addtree \
+    --parameters=(gli_noquote_parameters (gli_quote $slkvs_principal $slkvs_store) (gli_quote $argv[1]) $escaped_tree)
+end
+
+function add_hook -a name prefix url --description "Post changes under prefix to url, as json. Hooks live in the store under \$hooks"
+  set tree (jq -nc --arg prefix $prefix --arg url $url '{prefix: $prefix, url: $url}')
+  addtree "\$hooks/$name" $tree
+end
+
+function hook_stub -a port --description "Stand in for a service that hooks post to, printing each post. Default port 8080"
+  test -n "$port"; or set port 8080
+  python3 -c '
+import http.server, sys
+class Stub(http.server.BaseHTTPRequestHandler):
+  def do_POST(self):
+    print(self.path, self.rfile.read(int(self.headers["content-length"])).decode(), flush=True)
+    self.send_response(204)
+    self.end_headers()
+http.server.HTTPServer(("", int(sys.argv[1])), Stub).serve_forever()
+' $port
 end
 
 function drop --description "Remove all key->values"
   golem-cli worker invoke-and-await \
     --component-name=slkvs \
     --worker-name=fst \
-    --function=golem:component/api/drop
+    --function=golem:component/admin/drop \
+    --parameters=(gli_parameters $slkvs_principal $slkvs_store)
 end
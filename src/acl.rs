@@ -0,0 +1,129 @@
+// Who may do what to which paths. A grant gives a principal a permission on
+// a path prefix, and everything below it.
+
+use std::collections::BTreeMap;
+
+use crate::tree::{DingString, SchemaPath};
+
+/// Each permission includes the ones before it, so write can also read, and
+/// admin can do anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Permission {
+  Read,
+  Write,
+  // change the acl, and the whole store eg drop and indexes
+  Admin,
+}
+
+impl std::fmt::Display for Permission {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Permission::Read => f.write_str("read"),
+      Permission::Write => f.write_str("write"),
+      Permission::Admin => f.write_str("admin"),
+    }
+  }
+}
+
+impl std::str::FromStr for Permission {
+  type Err = DingString;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s {
+      "read" => Ok(Permission::Read),
+      "write" => Ok(Permission::Write),
+      "admin" => Ok(Permission::Admin),
+      _ => Err(format!("{s} is not a permission").into()),
+    }
+  }
+}
+
+/// Grants by principal and prefix. Nothing is allowed without a grant.
+#[derive(Default)]
+pub struct Acl(BTreeMap<(String,SchemaPath),Permission>);
+
+impl Acl {
+  /// Replaces any grant the principal already has at exactly prefix.
+  pub fn grant(&mut self, principal: String, prefix: SchemaPath, permission: Permission) {
+    self.0.insert((principal, prefix), permission);
+  }
+
+  pub fn revoke(&mut self, principal: &str, prefix: &SchemaPath) -> Result<(), DingString> {
+    match self.0.remove(&(principal.to_string(), prefix.clone())) {
+      Some(_) => Ok(()),
+      None => Err(format!("{principal} has no grant at {prefix}").into()),
+    }
+  }
+
+  pub fn grants(&self) -> impl Iterator<Item=(&str,&SchemaPath,Permission)> {
+    self.0.iter().map(|((principal,prefix),permission)| (principal.as_str(), prefix, *permission))
+  }
+
+  /// Does principal have a grant anywhere at all?
+  pub fn knows(&self, principal: &str) -> bool {
+    self.grants_for(principal).next().is_some()
+  }
+
+  fn grants_for<'a>(&'a self, principal: &'a str) -> impl Iterator<Item=(&'a SchemaPath,Permission)> {
+    let lower = (principal.to_string(), SchemaPath::from(vec![]));
+    self.0
+      .range(lower..)
+      .take_while(move |((granted_to,_),_)| granted_to == principal)
+      .map(|((_,prefix),permission)| (prefix, *permission))
+  }
+
+  /// Is there a grant of at least needs at path, or anywhere above it?
+  pub fn allows(&self, principal: &str, path: &SchemaPath, needs: Permission) -> bool {
+    self.grants_for(principal)
+      .any(|(prefix,permission)| permission >= needs && path.steps().starts_with(prefix.steps()))
+  }
+
+  pub fn check(&self, principal: &str, path: &SchemaPath, needs: Permission) -> Result<(), DingString> {
+    if self.allows(principal, path, needs) {
+      Ok(())
+    } else {
+      Err(format!("{principal} may not {needs} {path}").into())
+    }
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn prefixes_and_levels() {
+    let mut acl = Acl::default();
+    acl.grant("ann".into(), "".into(), Permission::Admin);
+    acl.grant("bob".into(), "users".into(), Permission::Read);
+    acl.grant("bob".into(), "users/bob".into(), Permission::Write);
+    // sorts right after bob, so make sure bob's grants stop before these
+    acl.grant("bobby".into(), "public".into(), Permission::Write);
+
+    assert!(acl.allows("ann", &"anything/at/all".into(), Permission::Admin));
+
+    assert!(acl.allows("bob", &"users/ann/email".into(), Permission::Read));
+    assert!(!acl.allows("bob", &"users/ann/email".into(), Permission::Write));
+    assert!(acl.allows("bob", &"users/bob/email".into(), Permission::Write));
+    assert!(!acl.allows("bob", &"users/bob".into(), Permission::Admin));
+    // prefixes are whole steps, not text
+    assert!(!acl.allows("bob", &"users2".into(), Permission::Read));
+    assert!(!acl.allows("bob", &"public".into(), Permission::Read));
+    assert!(!acl.allows("bob", &"".into(), Permission::Read));
+
+    assert!(!acl.allows("cat", &"users".into(), Permission::Read));
+    assert!(!acl.knows("cat"));
+    assert!(acl.knows("bobby"));
+
+    let err = acl.check("bob", &"users/ann".into(), Permission::Write).unwrap_err();
+    assert_eq!(err.to_string(), "bob may not write users/ann");
+
+    acl.revoke("bob", &"users/bob".into()).unwrap();
+    assert!(!acl.allows("bob", &"users/bob/email".into(), Permission::Write));
+    assert!(acl.allows("bob", &"users/bob/email".into(), Permission::Read));
+    let err = acl.revoke("bob", &"users/bob".into()).unwrap_err();
+    assert_eq!(err.to_string(), "bob has no grant at users/bob");
+  }
+}
@@ -0,0 +1,255 @@
+// Differences between two subtrees, leaf by leaf. The subtrees can be in the
+// same store, eg staging/app and prod/app, or the same subtree at two
+// versions.
+
+use std::cmp::Ordering;
+
+use serde_json::{json, Value};
+
+use crate::tree::{Leaf, LeafPaths, SchemaPath};
+
+/// One leaf that differs, at path below both subtrees. before is None for an
+/// added leaf, after is None for a removed one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+  pub path: SchemaPath,
+  pub before: Option<Leaf<String>>,
+  pub after: Option<Leaf<String>>,
+}
+
+/// Merge two sorted runs of (path,leaf), both relative to their subtree, into
+/// the leaves that differ, in path order.
+pub fn merge<'a>(
+  a: impl Iterator<Item=(SchemaPath,&'a Leaf<String>)>,
+  b: impl Iterator<Item=(SchemaPath,&'a Leaf<String>)>,
+) -> Vec<Difference> {
+  let mut a = a.peekable();
+  let mut b = b.peekable();
+  let mut differences = vec![];
+
+  loop {
+    let order = match (a.peek(), b.peek()) {
+      (None, None) => break,
+      (Some(_), None) => Ordering::Less,
+      (None, Some(_)) => Ordering::Greater,
+      (Some((pa,_)), Some((pb,_))) => pa.cmp(pb),
+    };
+    match order {
+      Ordering::Less => {
+        let (path,leaf) = a.next().unwrap();
+        differences.push(Difference { path, before: Some(leaf.clone()), after: None });
+      },
+      Ordering::Greater => {
+        let (path,leaf) = b.next().unwrap();
+        differences.push(Difference { path, before: None, after: Some(leaf.clone()) });
+      },
+      Ordering::Equal => {
+        let (path,before) = a.next().unwrap();
+        let (_,after) = b.next().unwrap();
+        if before != after {
+          differences.push(Difference { path, before: Some(before.clone()), after: Some(after.clone()) });
+        }
+      },
+    }
+  }
+
+  differences
+}
+
+/// The json of the subtree at path, as gettree has it below path. null if
+/// there's nothing there.
+pub fn subtree_json(db: &LeafPaths, path: String) -> Value {
+  let steps = SchemaPath::from(path.as_str());
+  db.gettree(path, None)
+    .below(steps.steps())
+    .map_or(Value::Null, |subtree| subtree.to_json())
+}
+
+/// An RFC 6902 patch that turns before into after, eg two subtree_jsons.
+/// Things that are only on one side are added or removed whole, and a leaf
+/// that becomes an object or array, or the other way round, is replaced.
+/// Arrays are as gettree shows them, without gaps, so indexes in the patch
+/// are positions in those.
+pub fn to_patch(before: &Value, after: &Value) -> Value {
+  let mut ops = vec![];
+  patch_at(&mut String::new(), before, after, &mut ops);
+  Value::Array(ops)
+}
+
+fn patch_at(at: &mut String, before: &Value, after: &Value, ops: &mut Vec<Value>) {
+  // RFC 6901
+  fn step(at: &mut String, key: &str, f: impl FnOnce(&mut String)) {
+    let len = at.len();
+    at.push('/');
+    at.push_str(&key.replace('~', "~0").replace('/', "~1"));
+    f(at);
+    at.truncate(len);
+  }
+
+  match (before, after) {
+    _ if before == after => (),
+    (Value::Object(before), Value::Object(after)) => {
+      for key in before.keys().filter(|key| !after.contains_key(*key)) {
+        step(at, key, |at| ops.push(json!({"op": "remove", "path": at})));
+      }
+      for (key,value) in after {
+        step(at, key, |at| match before.get(key) {
+          Some(was) => patch_at(at, was, value, ops),
+          None => ops.push(json!({"op": "add", "path": at, "value": value})),
+        });
+      }
+    }
+    (Value::Array(before), Value::Array(after)) => {
+      for (i,(was,value)) in before.iter().zip(after).enumerate() {
+        step(at, &i.to_string(), |at| patch_at(at, was, value, ops));
+      }
+      // from the end, so the ones still to go don't move
+      for i in (after.len()..before.len()).rev() {
+        step(at, &i.to_string(), |at| ops.push(json!({"op": "remove", "path": at})));
+      }
+      // each one onto the end
+      for (i,value) in after.iter().enumerate().skip(before.len()) {
+        step(at, &i.to_string(), |at| ops.push(json!({"op": "add", "path": at, "value": value})));
+      }
+    }
+    _ => ops.push(json!({"op": "replace", "path": at, "value": after})),
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  use crate::tree::LeafPaths;
+
+  #[test]
+  fn subtrees() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("staging/app".into(), r#"{"name": "app", "replicas": 2, "hosts": ["a", "b"], "debug": true}"#.into()).unwrap();
+    leaf_paths.addtree("prod/app".into(), r#"{"name": "app", "replicas": 5, "hosts": ["a"], "a/b~c": null}"#.into()).unwrap();
+
+    let differences = leaf_paths.diff("staging/app".into(), "prod/app".into());
+    let shown = differences
+      .iter()
+      .map(|d| format!("{} {:?} {:?}", d.path, d.before, d.after))
+      .collect::<Vec<_>>();
+    assert_eq!(shown, vec![
+      r#"a/b~c None Some(Null)"#,
+      r#"debug Some(String("true")) None"#,
+      r#"hosts/1 Some(String("b")) None"#,
+      r#"replicas Some(Number("2")) Some(Number("5"))"#,
+    ]);
+
+    let (staging,prod) = (subtree_json(&leaf_paths, "staging/app".into()), subtree_json(&leaf_paths, "prod/app".into()));
+    assert_eq!(to_patch(&staging, &prod), json!([
+      {"op": "remove", "path": "/debug"},
+      {"op": "add", "path": "/a~1b~0c", "value": null},
+      {"op": "remove", "path": "/hosts/1"},
+      {"op": "replace", "path": "/replicas", "value": 5},
+    ]));
+
+    assert_eq!(leaf_paths.diff("prod/app".into(), "prod/app".into()), vec![]);
+    assert_eq!(leaf_paths.diff("nothing".into(), "prod/app/hosts".into()).len(), 1);
+  }
+
+  /// Applies patch to doc the way RFC 6902 says, failing where it says to
+  /// rather than making up what isn't there.
+  fn apply(mut doc: Value, patch: &Value) -> Result<Value, String> {
+    fn unescape(step: &str) -> String {
+      step.replace("~1", "/").replace("~0", "~")
+    }
+
+    for op in patch.as_array().unwrap() {
+      let path = op["path"].as_str().unwrap();
+      let value = op.get("value").cloned();
+      if path.is_empty() {
+        match op["op"].as_str().unwrap() {
+          "replace" | "add" => doc = value.unwrap(),
+          other => return Err(format!("can't {other} the whole document")),
+        }
+        continue
+      }
+      let (parent,last) = path.rsplit_once('/').unwrap();
+      let last = unescape(last);
+      let parent = doc.pointer_mut(parent).ok_or_else(|| format!("no parent for {path}"))?;
+      match (op["op"].as_str().unwrap(), parent) {
+        ("add", Value::Object(map)) => { map.insert(last, value.unwrap()); }
+        ("remove", Value::Object(map)) => { map.remove(&last).ok_or_else(|| format!("nothing to remove at {path}"))?; }
+        ("replace", Value::Object(map)) => *map.get_mut(&last).ok_or_else(|| format!("nothing to replace at {path}"))? = value.unwrap(),
+        (op, Value::Array(ary)) => {
+          let i = last.parse::<usize>().map_err(|_| format!("{last} is not an array index"))?;
+          match op {
+            "add" if i <= ary.len() => ary.insert(i, value.unwrap()),
+            "remove" if i < ary.len() => { ary.remove(i); }
+            "replace" if i < ary.len() => ary[i] = value.unwrap(),
+            _ => return Err(format!("{op} at {path} is out of range")),
+          }
+        }
+        (op, _) => return Err(format!("can't {op} at {path}, its parent is a value")),
+      }
+    }
+    Ok(doc)
+  }
+
+  #[test]
+  fn patches() {
+    let pairs = [
+      // adds below objects that aren't there yet
+      (r#"{"a": 1}"#, r#"{"a": 1, "x": {"y": {"z": 2}}}"#),
+      // removes whole array elements
+      (r#"{"hosts": [{"name": "a"}, {"name": "b"}]}"#, r#"{"hosts": [{"name": "a"}]}"#),
+      (r#"{"hosts": [1, 2, 3, 4]}"#, r#"{"hosts": [2]}"#),
+      // adds past what's there
+      (r#"{"hosts": [1]}"#, r#"{"hosts": [1, [2, 3], {"four": 4}]}"#),
+      (r#"{"hosts": [1]}"#, r#"{"hosts": {"0": 1}}"#),
+      // a leaf becoming a subtree and back
+      (r#"{"a": 1, "b": {"c": 2}}"#, r#"{"a": {"b": 2}, "b": 3}"#),
+      (r#"{"a~/b": [null, "x"]}"#, r#"{"a~/b": [null, "y"], "c/d": "z"}"#),
+      (r#"{"a": 1}"#, r#"{"a": 1}"#),
+    ];
+    for (before,after) in pairs {
+      let mut leaf_paths = LeafPaths::new();
+      leaf_paths.addtree("a/before".into(), before.into()).unwrap();
+      leaf_paths.addtree("b/after".into(), after.into()).unwrap();
+      let (before,after) = (subtree_json(&leaf_paths, "a/before".into()), subtree_json(&leaf_paths, "b/after".into()));
+
+      for (from,to) in [(&before,&after), (&after,&before)] {
+        let patch = to_patch(from, to);
+        assert_eq!(apply(from.clone(), &patch).as_ref(), Ok(to), "{from} to {to} with {patch}");
+      }
+    }
+
+    // arrays with gaps are patched as gettree shows them
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.add("a/hosts/3".into(), "x".into());
+    leaf_paths.add("b/hosts/0".into(), "x".into());
+    leaf_paths.add("b/hosts/7".into(), "y".into());
+    let (a,b) = (subtree_json(&leaf_paths, "a".into()), subtree_json(&leaf_paths, "b".into()));
+    let patch = to_patch(&a, &b);
+    assert_eq!(patch, json!([{"op": "add", "path": "/hosts/1", "value": "y"}]));
+    assert_eq!(apply(a, &patch), Ok(b.clone()));
+
+    // from nothing, and to nothing
+    let nothing = subtree_json(&leaf_paths, "nothing".into());
+    assert_eq!(nothing, Value::Null);
+    assert_eq!(apply(nothing.clone(), &to_patch(&nothing, &b)), Ok(b.clone()));
+    assert_eq!(to_patch(&b, &nothing), json!([{"op": "replace", "path": "", "value": null}]));
+  }
+
+  #[test]
+  fn versions() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("app".into(), r#"{"name": "app", "replicas": 2}"#.into()).unwrap();
+    let before = leaf_paths.view();
+    leaf_paths.setvalue("app/replicas".into(), Leaf::Number("3".into())).unwrap();
+    leaf_paths.add("other".into(), "not in app".into());
+
+    assert_eq!(before.diff("app".into(), &leaf_paths), vec![Difference {
+      path: "replicas".into(),
+      before: Some(Leaf::Number("2".into())),
+      after: Some(Leaf::Number("3".into())),
+    }]);
+  }
+}
@@ -0,0 +1,278 @@
+// A dump of a whole store, as json. It has everything needed to make the
+// store again exactly as it was: every path with its typed value, the index
+// definitions, the acl, the revision, and the history. Not the feed or what
+// there is to undo, which start again from the import.
+//
+// Steps keep their kind, so the key "0" and the index 0 stay different, and
+// numbers keep their text.
+//
+//   {
+//     "format": "slkvs-dump",
+//     "version": 2,
+//     "revision": 3,
+//     "indexes": [{"name": "emails", "kind": "value", "pattern": "users/*/email"}],
+//     "acl": [{"principal": "ann", "prefix": "", "permission": "admin"}],
+//     "entries": [[["users", 0, "email"], {"str": "ann@example.com"}]],
+//     "history": {"gone": 0, "paths": [
+//       {"path": ["users", 0, "email"], "forgotten": false, "changes": [[1700000000000, {"str": "ann@example.com"}]]}
+//     ]}
+//   }
+//
+// Version 1 had no history.
+
+use serde_json::{json, Value};
+
+use crate::acl::{Acl, Permission};
+use crate::history::{Change, PathHistory};
+use crate::index::IndexKind;
+use crate::stores::Store;
+use crate::tree::{DingString, Leaf, LeafPaths, SchemaPath, Step};
+
+const FORMAT: &str = "slkvs-dump";
+const VERSION: u64 = 2;
+
+fn leaf_to_json(leaf: &Leaf<String>) -> Value {
+  match leaf {
+    Leaf::String(v) => json!({"str": v}),
+    Leaf::Number(v) => json!({"num": v}),
+    Leaf::Boolean(v) => json!({"boolean": v}),
+    Leaf::Null => json!({"null": null}),
+  }
+}
+
+fn path_to_json(path: &SchemaPath) -> Value {
+  path.steps()
+    .iter()
+    .map(|step| match step {
+      Step::Key(k) => json!(k),
+      Step::Index(i) => json!(i),
+    })
+    .collect()
+}
+
+/// Everything in store, as a dump.
+pub fn export(store: &Store) -> String {
+  let indexes = store.db
+    .index_definitions()
+    .map(|(name,kind,pattern)| {
+      let kind = match kind {
+        IndexKind::Value => "value",
+        IndexKind::Number => "number",
+      };
+      json!({"name": name, "kind": kind, "pattern": pattern.to_string()})
+    })
+    .collect::<Vec<_>>();
+
+  let acl = store.acl
+    .grants()
+    .map(|(principal,prefix,permission)| json!({
+      "principal": principal,
+      "prefix": prefix.to_string(),
+      "permission": permission.to_string(),
+    }))
+    .collect::<Vec<_>>();
+
+  let entries = store.db.paths
+    .iter()
+    .map(|(path,leaf)| json!([path_to_json(path), leaf_to_json(leaf)]))
+    .collect::<Vec<_>>();
+
+  let history = store.db
+    .history_log()
+    .paths()
+    .map(|(path,history)| {
+      let changes = history.changes
+        .iter()
+        .map(|change| json!([change.at, change.leaf.as_ref().map(leaf_to_json)]))
+        .collect::<Vec<_>>();
+      json!({"path": path_to_json(path), "forgotten": history.forgotten, "changes": changes})
+    })
+    .collect::<Vec<_>>();
+
+  json!({
+    "format": FORMAT,
+    "version": VERSION,
+    "revision": store.db.revision(),
+    "indexes": indexes,
+    "acl": acl,
+    "entries": entries,
+    "history": {"gone": store.db.history_log().gone(), "paths": history},
+  }).to_string()
+}
+
+fn bad(what: &str) -> DingString {
+  format!("bad dump: {what}").into()
+}
+
+fn field<'a>(obj: &'a Value, name: &str) -> Result<&'a Value, DingString> {
+  obj.get(name).ok_or_else(|| bad(&format!("missing {name}")))
+}
+
+fn str_field<'a>(obj: &'a Value, name: &str) -> Result<&'a str, DingString> {
+  field(obj, name)?.as_str().ok_or_else(|| bad(&format!("{name} is not a string")))
+}
+
+fn list_field<'a>(obj: &'a Value, name: &str) -> Result<&'a Vec<Value>, DingString> {
+  field(obj, name)?.as_array().ok_or_else(|| bad(&format!("{name} is not a list")))
+}
+
+fn path_from_json(path: &Value) -> Result<SchemaPath, DingString> {
+  let steps = path.as_array().ok_or_else(|| bad("path is not a list"))?;
+  steps
+    .iter()
+    .map(|step| match step {
+      Value::String(k) => Ok(Step::Key(k.clone())),
+      Value::Number(i) => i.as_u64()
+        .map(|i| Step::Index(i as usize))
+        .ok_or_else(|| bad(&format!("step {i} is not an index"))),
+      other => Err(bad(&format!("step {other} is neither key nor index"))),
+    })
+    .collect::<Result<Vec<_>,_>>()
+    .map(SchemaPath::from)
+}
+
+fn leaf_from_json(leaf: &Value) -> Result<Leaf<String>, DingString> {
+  let Some((kind,v)) = leaf.as_object().filter(|obj| obj.len() == 1).and_then(|obj| obj.iter().next()) else {
+    return Err(bad(&format!("value {leaf} should have exactly one type")))
+  };
+  match (kind.as_str(),v) {
+    ("str", Value::String(v)) => Ok(Leaf::String(v.clone())),
+    ("num", Value::String(v)) if v.parse::<serde_json::Number>().is_ok() => Ok(Leaf::Number(v.clone())),
+    ("boolean", Value::Bool(v)) => Ok(Leaf::Boolean(*v)),
+    ("null", Value::Null) => Ok(Leaf::Null),
+    _ => Err(bad(&format!("value {leaf} is not a str num boolean or null"))),
+  }
+}
+
+fn path_history_from_json(history: &Value) -> Result<(SchemaPath,PathHistory), DingString> {
+  let path = path_from_json(field(history, "path")?)?;
+  let forgotten = field(history, "forgotten")?.as_bool().ok_or_else(|| bad("forgotten is not a boolean"))?;
+  let changes = list_field(history, "changes")?
+    .iter()
+    .map(|change| match change.as_array().map(Vec::as_slice) {
+      Some([at,leaf]) => Ok(Change {
+        at: at.as_u64().ok_or_else(|| bad(&format!("change at {at} is not a time")))?,
+        leaf: match leaf {
+          Value::Null => None,
+          leaf => Some(leaf_from_json(leaf)?),
+        },
+      }),
+      _ => Err(bad(&format!("change {change} should be [at, value]"))),
+    })
+    .collect::<Result<_,_>>()?;
+  Ok((path, PathHistory { forgotten, changes }))
+}
+
+/// Make a store from a dump. Nothing is made unless all of the dump is good.
+pub fn import(dump: &str) -> Result<Store, DingString> {
+  let dump: Value = serde_json::from_str(dump)?;
+
+  if str_field(&dump, "format")? != FORMAT {
+    return Err(bad(&format!("format should be {FORMAT}")))
+  }
+  let version = field(&dump, "version")?.as_u64().ok_or_else(|| bad("version is not a number"))?;
+  if !(1..=VERSION).contains(&version) {
+    return Err(format!("can't import dump version {version}, newest is {VERSION}").into())
+  }
+  let revision = field(&dump, "revision")?.as_u64().ok_or_else(|| bad("revision is not a number"))?;
+
+  let mut db = LeafPaths::new();
+  let entries = list_field(&dump, "entries")?
+    .iter()
+    .map(|entry| match entry.as_array().map(Vec::as_slice) {
+      Some([path,leaf]) => Ok((path_from_json(path)?, Some(leaf_from_json(leaf)?))),
+      _ => Err(bad(&format!("entry {entry} should be [path, value]"))),
+    })
+    .collect::<Result<Vec<_>,_>>()?;
+  db.apply(entries);
+
+  for index in list_field(&dump, "indexes")? {
+    let name = str_field(index, "name")?.to_string();
+    let pattern = str_field(index, "pattern")?.to_string();
+    match str_field(index, "kind")? {
+      "value" => db.addindex(name, pattern)?,
+      "number" => db.addnumindex(name, pattern)?,
+      kind => return Err(bad(&format!("{kind} is not a kind of index"))),
+    }
+  }
+
+  let mut acl = Acl::default();
+  for grant in list_field(&dump, "acl")? {
+    let principal = str_field(grant, "principal")?.to_string();
+    let prefix = SchemaPath::from(str_field(grant, "prefix")?);
+    let permission: Permission = str_field(grant, "permission")?.parse()?;
+    acl.grant(principal, prefix, permission);
+  }
+
+  // after the entries, which make history of their own
+  if version >= 2 {
+    let history = field(&dump, "history")?;
+    let gone = field(history, "gone")?.as_u64().ok_or_else(|| bad("gone is not a number"))?;
+    let paths = list_field(history, "paths")?
+      .iter()
+      .map(path_history_from_json)
+      .collect::<Result<Vec<_>,_>>()?;
+    db.history_log_mut().restore(paths, gone);
+  }
+
+  // last, because everything above bumps it
+  db.set_revision(revision);
+  Ok(Store::new(db, acl))
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn round_trip() {
+    let mut db = LeafPaths::new();
+    db.addtree("".into(), r#"{"users": [{"email": "ann@example.com", "admin": true, "boss": null}], "0": "key"}"#.into()).unwrap();
+    // addtree would parse this to 1.5
+    db.setvalue("users/0/age".into(), Leaf::Number("1.50".into())).unwrap();
+    db.add("temp".into(), "x".into());
+    db.delete("temp".into());
+    db.addindex("emails".into(), "users/*/email".into()).unwrap();
+    db.addnumindex("ages".into(), "users/*/age".into()).unwrap();
+    let mut acl = Acl::default();
+    acl.grant("ann".into(), "".into(), Permission::Admin);
+    acl.grant("bob".into(), "users/0".into(), Permission::Read);
+    let store = Store::new(db, acl);
+
+    let dump = export(&store);
+    let restored = import(&dump).unwrap();
+    assert_eq!(export(&restored), dump);
+
+    assert_eq!(restored.db.revision(), store.db.revision());
+    assert_eq!(restored.db.getvalue("users/0/age".into()), Some(Leaf::Number("1.50".into())));
+    assert_eq!(restored.db.getvalue("users/0/boss".into()), Some(Leaf::Null));
+    // the key "0" is not the index 0
+    assert_eq!(restored.db.paths.get(&SchemaPath::singleton(Step::Key("0".into()))), Some(&Leaf::String("key".into())));
+    assert_eq!(restored.db.lookup("emails".into(), "ann@example.com".into()).unwrap(), vec!["users/0"]);
+    assert!(restored.acl.allows("bob", &"users/0/email".into(), Permission::Read));
+    // including for what's been deleted
+    assert_eq!(restored.db.history("temp".into(), 10), store.db.history("temp".into(), 10));
+    assert_eq!(restored.db.history("temp".into(), 10).len(), 2);
+  }
+
+  #[test]
+  fn bad_dumps() {
+    let err = |dump: &str| import(dump).err().unwrap().to_string();
+
+    assert_eq!(err(r#"{"format": "other"}"#), "bad dump: format should be slkvs-dump");
+    assert_eq!(err(r#"{"format": "slkvs-dump", "version": 3}"#), "can't import dump version 3, newest is 2");
+
+    let dump = |entries: &str| format!(r#"{{"format": "slkvs-dump", "version": 1, "revision": 0, "indexes": [], "acl": [], "entries": {entries}}}"#);
+    assert_eq!(err(&dump(r#"[[["a"], {"num": "abc"}]]"#)), r#"bad dump: value {"num":"abc"} is not a str num boolean or null"#);
+    assert_eq!(err(&dump(r#"[[["a", -1], {"null": null}]]"#)), "bad dump: step -1 is not an index");
+    assert_eq!(err(&dump(r#"[["a"]]"#)), r#"bad dump: entry ["a"] should be [path, value]"#);
+    assert!(import(&dump("[]")).is_ok());
+
+    let history = |paths: &str| format!(r#"{{"format": "slkvs-dump", "version": 2, "revision": 0, "indexes": [], "acl": [], "entries": [], "history": {{"gone": 0, "paths": {paths}}}}}"#);
+    assert_eq!(err(&history(r#"[{"path": ["a"], "forgotten": false, "changes": [["soon", null]]}]"#)), r#"bad dump: change at "soon" is not a time"#);
+    assert_eq!(err(&history(r#"[{"path": ["a"], "forgotten": false, "changes": [[1]]}]"#)), "bad dump: change [1] should be [at, value]");
+    assert_eq!(err(r#"{"format": "slkvs-dump", "version": 2, "revision": 0, "indexes": [], "acl": [], "entries": []}"#), "bad dump: missing history");
+  }
+}
@@ -0,0 +1,148 @@
+// The changes to a store in order, for caches and anything else downstream
+// that polls for updates. Each change is numbered with the revision it made,
+// so a consumer can remember the last one it saw and carry on from there,
+// even after a restart. Only the last few are kept.
+
+use std::collections::VecDeque;
+
+use crate::tree::{DingString, Leaf, SchemaPath};
+
+/// Changes kept for the feed.
+pub const FEED_LIMIT: usize = 4096;
+
+/// Revision seq put leaf at path. None means path and everything below it
+/// was deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedChange {
+  pub seq: u64,
+  pub path: SchemaPath,
+  pub leaf: Option<Leaf<String>>,
+}
+
+impl FeedChange {
+  /// Does this change anything at or below prefix?
+  pub fn touches(&self, prefix: &SchemaPath) -> bool {
+    self.path.steps().starts_with(prefix.steps())
+      // deleting a parent deletes what's under prefix too
+      || (self.leaf.is_none() && prefix.steps().starts_with(self.path.steps()))
+  }
+}
+
+pub struct Feed {
+  limit: usize,
+  // every change after this is kept
+  since: u64,
+  // oldest first
+  changes: VecDeque<FeedChange>,
+}
+
+impl Default for Feed {
+  fn default() -> Self {
+    Self::new(FEED_LIMIT)
+  }
+}
+
+impl Feed {
+  pub fn new(limit: usize) -> Self {
+    Self { limit, since: 0, changes: VecDeque::new() }
+  }
+
+  pub fn record(&mut self, seq: u64, path: &SchemaPath, leaf: Option<&Leaf<String>>) {
+    if self.limit == 0 { return }
+    self.changes.push_back(FeedChange { seq, path: path.clone(), leaf: leaf.cloned() });
+    while self.changes.len() > self.limit {
+      if let Some(dropped) = self.changes.pop_front() {
+        self.since = dropped.seq;
+      }
+    }
+  }
+
+  /// Up to limit changes at or below prefix after since, oldest first, and
+  /// the seq to ask for next time. Fails if changes after since have been
+  /// dropped, or if since is later than latest, eg because the store was
+  /// restored from before since. Either way the consumer has to start again.
+  pub fn after(&self, since: u64, latest: u64, prefix: &SchemaPath, limit: usize) -> Result<(Vec<&FeedChange>,u64), DingString> {
+    if since < self.since {
+      return Err(format!("changes after {since} are gone, the oldest kept are after {}", self.since).into())
+    }
+    if since > latest {
+      return Err(format!("{since} is after the latest change {latest}").into())
+    }
+    let mut changes = self.changes
+      .iter()
+      .skip_while(|change| change.seq <= since)
+      .filter(|change| change.touches(prefix));
+    let page = changes.by_ref().take(limit).collect::<Vec<_>>();
+    // Skip past changes outside prefix too, unless there might be more
+    // under it.
+    let next = match page.last() {
+      Some(last) if changes.next().is_some() => last.seq,
+      None if limit == 0 => since,
+      _ => latest,
+    };
+    Ok((page, next))
+  }
+
+  /// Forget every change, and carry on after since. eg because everything
+  /// up to since came from somewhere else.
+  pub fn restart(&mut self, since: u64) {
+    self.since = since;
+    self.changes.clear();
+  }
+
+  pub fn since(&self) -> u64 {
+    self.since
+  }
+
+  pub fn changes(&self) -> impl Iterator<Item=&FeedChange> {
+    self.changes.iter()
+  }
+
+  /// Put back what since and changes gave, eg from a snapshot.
+  pub fn restore(&mut self, since: u64, changes: impl IntoIterator<Item=FeedChange>) {
+    self.since = since;
+    self.changes = changes.into_iter().collect();
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn polling() {
+    let leaf = |v: &str| Some(Leaf::String(v.to_string()));
+    let seqs = |page: (Vec<&FeedChange>,u64)| (page.0.iter().map(|change| change.seq).collect::<Vec<_>>(), page.1);
+
+    let mut feed = Feed::new(4);
+    feed.record(1, &"app/name".into(), leaf("app").as_ref());
+    feed.record(2, &"other".into(), leaf("x").as_ref());
+    feed.record(3, &"app/name".into(), None);
+    feed.record(4, &"app/hosts/0".into(), leaf("a").as_ref());
+
+    let app = SchemaPath::from("app");
+    assert_eq!(seqs(feed.after(0, 4, &app, 10).unwrap()), (vec![1, 3, 4], 4));
+    // more to come, so carry on from the last one returned
+    assert_eq!(seqs(feed.after(0, 4, &app, 2).unwrap()), (vec![1, 3], 3));
+    assert_eq!(seqs(feed.after(3, 4, &app, 2).unwrap()), (vec![4], 4));
+    assert_eq!(seqs(feed.after(4, 4, &app, 2).unwrap()), (vec![], 4));
+    assert_eq!(seqs(feed.after(0, 4, &app, 0).unwrap()), (vec![], 0));
+    // nothing under here, but still skips ahead
+    assert_eq!(seqs(feed.after(0, 4, &"nothing".into(), 2).unwrap()), (vec![], 4));
+
+    // deleting everything touches every prefix
+    feed.record(5, &"".into(), None);
+    assert_eq!(seqs(feed.after(4, 5, &"app/name".into(), 10).unwrap()), (vec![5], 5));
+
+    // 1 fell off the front
+    assert_eq!(feed.after(0, 5, &app, 10).unwrap_err().to_string(), "changes after 0 are gone, the oldest kept are after 1");
+    assert_eq!(feed.after(9, 5, &app, 10).unwrap_err().to_string(), "9 is after the latest change 5");
+    assert_eq!(seqs(feed.after(1, 5, &app, 10).unwrap()), (vec![3, 4, 5], 5));
+
+    feed.restart(20);
+    assert_eq!(seqs(feed.after(20, 20, &app, 10).unwrap()), (vec![], 20));
+    assert!(feed.after(5, 20, &app, 10).is_err());
+  }
+}
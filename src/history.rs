@@ -0,0 +1,204 @@
+// What each path had in it, and when. Only the last few changes to each path
+// are kept, so this doesn't grow without end for a path that changes a lot.
+// Nor for a store where lots of paths come and go, since only the history of
+// the last few deleted paths is kept.
+
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+
+use crate::tree::{DingString, Leaf, SchemaPath};
+
+/// Changes kept for each path.
+pub const HISTORY_LIMIT: usize = 32;
+
+/// Deleted paths whose history is kept.
+pub const DELETED_LIMIT: usize = 1024;
+
+/// From at onwards, the path had leaf in it. None for deleted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+  // milliseconds since the unix epoch
+  pub at: u64,
+  pub leaf: Option<Leaf<String>>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PathHistory {
+  // Some changes before these have been dropped, or were never known. So
+  // there's no telling what the path had before the first of these.
+  pub forgotten: bool,
+  // oldest first
+  pub changes: VecDeque<Change>,
+}
+
+pub struct History {
+  limit: usize,
+  deleted_limit: usize,
+  paths: BTreeMap<SchemaPath,PathHistory>,
+  // (when, path) of each path whose last change was a delete, oldest first
+  deleted: BTreeSet<(u64,SchemaPath)>,
+  // The history of paths deleted at or before this has been dropped, so
+  // there's no telling what any path without history had before then.
+  gone: u64,
+}
+
+impl Default for History {
+  fn default() -> Self {
+    Self::new(HISTORY_LIMIT, DELETED_LIMIT)
+  }
+}
+
+impl History {
+  pub fn new(limit: usize, deleted_limit: usize) -> Self {
+    Self { limit, deleted_limit, paths: BTreeMap::new(), deleted: BTreeSet::new(), gone: 0 }
+  }
+
+  /// path has leaf in it from at onwards.
+  pub fn record(&mut self, path: &SchemaPath, at: u64, leaf: Option<&Leaf<String>>) {
+    let history = self.paths.entry(path.clone()).or_default();
+    // writing the same thing again isn't a change
+    if history.changes.back().map(|change| change.leaf.as_ref()) == Some(leaf) {
+      return
+    }
+    if let Some(Change { at: deleted_at, leaf: None }) = history.changes.back() {
+      self.deleted.remove(&(*deleted_at, path.clone()));
+    }
+    history.changes.push_back(Change { at, leaf: leaf.cloned() });
+    while history.changes.len() > self.limit {
+      history.changes.pop_front();
+      history.forgotten = true;
+    }
+
+    if leaf.is_none() {
+      self.deleted.insert((at, path.clone()));
+      self.forget_deleted();
+    }
+  }
+
+  // Drop the history of the longest deleted paths, down to deleted_limit.
+  fn forget_deleted(&mut self) {
+    while self.deleted.len() > self.deleted_limit {
+      let Some((at,path)) = self.deleted.pop_first() else { break };
+      self.paths.remove(&path);
+      self.gone = self.gone.max(at);
+    }
+  }
+
+  /// The last n changes to path, newest first.
+  pub fn recent(&self, path: &SchemaPath, n: usize) -> Vec<&Change> {
+    match self.paths.get(path) {
+      Some(history) => history.changes.iter().rev().take(n).collect(),
+      None => vec![],
+    }
+  }
+
+  /// What path had in it at time at. Fails if that's from before the
+  /// changes that are kept.
+  pub fn at(&self, path: &SchemaPath, at: u64) -> Result<Option<&Leaf<String>>, DingString> {
+    // it might have been deleted, and its history dropped
+    let unknown = || Err(format!("history of deleted paths only goes back to {}", self.gone).into());
+    let Some(history) = self.paths.get(path) else {
+      return if at <= self.gone && self.gone > 0 { unknown() } else { Ok(None) }
+    };
+    match history.changes.iter().rev().find(|change| change.at <= at) {
+      Some(change) => Ok(change.leaf.as_ref()),
+      None if !history.forgotten && at <= self.gone && self.gone > 0 => unknown(),
+      None if !history.forgotten => Ok(None),
+      None => match history.changes.front() {
+        Some(oldest) => Err(format!("history of {path} only goes back to {}", oldest.at).into()),
+        None => Err(format!("no history of {path}").into()),
+      },
+    }
+  }
+
+  pub fn paths(&self) -> impl Iterator<Item=(&SchemaPath,&PathHistory)> {
+    self.paths.iter()
+  }
+
+  /// When the history of deleted paths was last dropped, or 0 if it never
+  /// has been.
+  pub fn gone(&self) -> u64 {
+    self.gone
+  }
+
+  /// Put back what paths and gone gave, eg from a snapshot.
+  pub fn restore(&mut self, paths: impl IntoIterator<Item=(SchemaPath,PathHistory)>, gone: u64) {
+    self.paths = paths.into_iter().collect();
+    self.gone = gone;
+    self.deleted = self.paths
+      .iter()
+      .filter_map(|(path,history)| match history.changes.back() {
+        Some(Change { at, leaf: None }) => Some((*at, path.clone())),
+        _ => None,
+      })
+      .collect();
+    self.forget_deleted();
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn bounded() {
+    let path = SchemaPath::from("motd");
+    let leaf = |v: &str| Leaf::String(v.to_string());
+
+    let mut history = History::new(3, 2);
+    history.record(&path, 10, Some(&leaf("uno")));
+    history.record(&path, 20, Some(&leaf("due")));
+    history.record(&path, 20, Some(&leaf("due")));
+    history.record(&path, 30, None);
+
+    let recent = history.recent(&path, 2).into_iter().map(|change| change.at).collect::<Vec<_>>();
+    assert_eq!(recent, vec![30, 20]);
+
+    assert_eq!(history.at(&path, 5).unwrap(), None);
+    assert_eq!(history.at(&path, 15).unwrap(), Some(&leaf("uno")));
+    assert_eq!(history.at(&path, 20).unwrap(), Some(&leaf("due")));
+    assert_eq!(history.at(&path, 35).unwrap(), None);
+    assert_eq!(history.at(&"other".into(), 35).unwrap(), None);
+
+    // pushes uno out, so now nothing is known before due
+    history.record(&path, 40, Some(&leaf("tre")));
+    assert_eq!(history.recent(&path, 10).len(), 3);
+    assert_eq!(history.at(&path, 25).unwrap(), Some(&leaf("due")));
+    let err = history.at(&path, 15).unwrap_err();
+    assert_eq!(err.to_string(), "history of motd only goes back to 20");
+  }
+
+  #[test]
+  fn deleted() {
+    let leaf = Leaf::String("x".to_string());
+    let mut history = History::new(3, 2);
+    for (at,path) in [(10, "uno"), (20, "due"), (30, "tre")] {
+      history.record(&path.into(), at, Some(&leaf));
+    }
+    history.record(&"uno".into(), 40, None);
+    history.record(&"due".into(), 50, None);
+    // back again, so not deleted any more
+    history.record(&"due".into(), 60, Some(&leaf));
+    history.record(&"tre".into(), 70, None);
+    assert_eq!(history.paths().count(), 3);
+
+    // one too many deleted, so the longest deleted goes
+    history.record(&"due".into(), 80, None);
+    assert_eq!(history.paths().map(|(path,_)| path.to_string()).collect::<Vec<_>>(), vec!["due", "tre"]);
+    assert_eq!(history.gone(), 40);
+    assert_eq!(history.at(&"uno".into(), 45).unwrap(), None);
+    let err = history.at(&"uno".into(), 15).unwrap_err();
+    assert_eq!(err.to_string(), "history of deleted paths only goes back to 40");
+    // nor what anything else had that far back
+    assert!(history.at(&"tre".into(), 5).is_err());
+    assert_eq!(history.at(&"tre".into(), 35).unwrap(), Some(&leaf));
+
+    // and the same again after a restore
+    let paths = history.paths().map(|(path,history)| (path.clone(), history.clone())).collect::<Vec<_>>();
+    let mut restored = History::new(3, 1);
+    restored.restore(paths, history.gone());
+    assert_eq!(restored.paths().map(|(path,_)| path.to_string()).collect::<Vec<_>>(), vec!["due"]);
+    assert_eq!(restored.gone(), 70);
+  }
+}
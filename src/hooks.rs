@@ -0,0 +1,339 @@
+// Webhooks. A store's hooks are kept in the store itself, under $hooks, eg
+//
+//   $hooks/deploys/prefix  config
+//   $hooks/deploys/url     https://example.com/deploys
+//
+// so they're set up with add or addtree. A hook is sent everything under its
+// prefix, whoever may read it, so only admins can change them. After
+// anything changes under a hook's prefix, the changes are posted to its url
+// as json:
+//
+//   {"store": "default", "hook": "deploys", "truncated": false, "changes": [
+//     {"seq": 7, "path": "config/replicas", "value": 3},
+//     {"seq": 8, "path": "config/debug", "deleted": true}
+//   ]}
+//
+// The changes come from the feed, so a change too big for the feed only
+// posts what the feed kept, with truncated true to say so.
+//
+// Posting could take a while, and a write shouldn't wait on somebody else's
+// service, so a write only queues its posts. admin.deliverhooks sends them,
+// oldest first, until BUDGET is up, so it's meant to be called every so often.
+// A post that still fails after ATTEMPTS tries is dropped, and the store
+// remembers why until a post to that hook works.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::tree::{DingString, LeafPaths, SchemaPath};
+
+/// Where a store keeps its hooks.
+pub const HOOKS: &str = "$hooks";
+
+/// Tries for each post before giving up.
+pub const ATTEMPTS: usize = 3;
+
+/// Wait before the second try, doubled for each one after.
+pub const BACKOFF: Duration = Duration::from_millis(500);
+
+/// How long to wait to connect, and then for the response to start.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// No post or retry is started after this long, so a delivery takes at most
+/// this plus one post's timeouts.
+pub const BUDGET: Duration = Duration::from_secs(30);
+
+/// Posts waiting on a store before the oldest are dropped.
+pub const QUEUE_LIMIT: usize = 1024;
+
+/// Something that can post json to a url.
+pub trait Sender {
+  fn post(&mut self, url: &str, body: &str) -> Result<(), DingString>;
+  fn pause(&mut self, how_long: Duration);
+  /// Time since some fixed point, only ever going forward.
+  fn now(&mut self) -> Duration;
+}
+
+/// Changes waiting to be posted to a hook.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Post {
+  pub hook: String,
+  pub url: String,
+  pub body: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Hook {
+  pub name: String,
+  pub prefix: SchemaPath,
+  pub url: String,
+}
+
+/// The hooks in db. Ones without both a prefix and a url are left out.
+pub fn hooks(db: &LeafPaths) -> Vec<Hook> {
+  db.records(HOOKS, "url")
+    .into_iter()
+    .map(|(name,prefix,url)| Hook { name, prefix, url })
+    .collect()
+}
+
+/// A post for each hook with changes after revision since.
+pub fn posts(store: &str, db: &LeafPaths, since: u64) -> Vec<Post> {
+  if db.revision() == since { return vec![] }
+  // the feed has already dropped some of what changed
+  let truncated = since < db.feed_log().since();
+  hooks(db)
+    .into_iter()
+    .filter_map(|hook| {
+      let changes = db.feed_log()
+        .changes()
+        .filter(|change| change.seq > since && change.touches(&hook.prefix))
+        .map(|change| match &change.leaf {
+          Some(leaf) => json!({"seq": change.seq, "path": change.path.to_string(), "value": Value::from(leaf)}),
+          None => json!({"seq": change.seq, "path": change.path.to_string(), "deleted": true}),
+        })
+        .collect::<Vec<_>>();
+      if changes.is_empty() { return None }
+      let body = json!({"store": store, "hook": hook.name, "truncated": truncated, "changes": changes});
+      Some(Post { hook: hook.name, url: hook.url, body: body.to_string() })
+    })
+    .collect()
+}
+
+/// Post body to url, trying up to ATTEMPTS times, with a pause between tries,
+/// but not trying again once it's past deadline. The last error if none of
+/// them worked.
+pub fn deliver(sender: &mut impl Sender, url: &str, body: &str, deadline: Duration) -> Result<(), DingString> {
+  let mut rv = Ok(());
+  let mut pause = BACKOFF;
+  for attempt in 0..ATTEMPTS {
+    if attempt > 0 {
+      if sender.now() + pause > deadline { break }
+      sender.pause(pause);
+      pause *= 2;
+    }
+    rv = sender.post(url, body);
+    if rv.is_ok() { break }
+  }
+  rv
+}
+
+/// Deliver queued posts, oldest first, until there are none left or BUDGET
+/// is up. (hook, result) for each post tried, the rest stay queued.
+pub fn deliver_queued(sender: &mut impl Sender, queue: &mut VecDeque<Post>) -> Vec<(String,Result<(),DingString>)> {
+  let deadline = sender.now() + BUDGET;
+  let mut tried = vec![];
+  while sender.now() < deadline {
+    let Some(post) = queue.pop_front() else { break };
+    tried.push((post.hook, deliver(sender, &post.url, &post.body, deadline)));
+  }
+  tried
+}
+
+/// (https, authority, path with query) for an http or https url. The
+/// fragment is left off, as it's never sent.
+pub fn split_url(url: &str) -> Result<(bool,&str,String), DingString> {
+  let (https,rst) = match url.split_once("://") {
+    Some(("http",rst)) => (false, rst),
+    Some(("https",rst)) => (true, rst),
+    _ => return Err(format!("{url} is not an http or https url").into()),
+  };
+  let rst = rst.split_once('#').map_or(rst, |(rst,_)| rst);
+  let (authority,path) = rst.split_at(rst.find(['/', '?']).unwrap_or(rst.len()));
+  if authority.is_empty() {
+    return Err(format!("{url} has no host").into())
+  }
+  let path = if path.starts_with('/') { path.to_string() } else { format!("/{path}") };
+  Ok((https, authority, path))
+}
+
+/// Posts with wasi:http, which golem provides.
+pub struct WasiHttp;
+
+impl Sender for WasiHttp {
+  fn post(&mut self, url: &str, body: &str) -> Result<(), DingString> {
+    use crate::bindings::wasi::http::outgoing_handler;
+    use crate::bindings::wasi::http::types::{Fields, Method, OutgoingBody, OutgoingRequest, RequestOptions, Scheme};
+
+    let failed = |what: &str, err: &dyn std::fmt::Debug| DingString::from(format!("post to {url} failed {what}: {err:?}"));
+
+    let (https,authority,path) = split_url(url)?;
+    let scheme = if https { Scheme::Https } else { Scheme::Http };
+
+    let headers = Fields::from_list(&[("content-type".to_string(), b"application/json".to_vec())])
+      .map_err(|err| failed("making headers", &err))?;
+    let request = OutgoingRequest::new(headers);
+    request.set_method(&Method::Post).map_err(|err| failed("setting method", &err))?;
+    request.set_scheme(Some(&scheme)).map_err(|err| failed("setting scheme", &err))?;
+    request.set_authority(Some(authority)).map_err(|err| failed("setting authority", &err))?;
+    request.set_path_with_query(Some(&path)).map_err(|err| failed("setting path", &err))?;
+
+    let outgoing = request.body().map_err(|err| failed("getting body", &err))?;
+    {
+      let stream = outgoing.write().map_err(|err| failed("writing body", &err))?;
+      // no more than 4096 bytes at a time
+      for chunk in body.as_bytes().chunks(4096) {
+        stream.blocking_write_and_flush(chunk).map_err(|err| failed("writing body", &err))?;
+      }
+    }
+    OutgoingBody::finish(outgoing, None).map_err(|err| failed("finishing body", &err))?;
+
+    let options = RequestOptions::new();
+    // an error only means the host doesn't support that timeout
+    let _ = options.set_connect_timeout(Some(TIMEOUT.as_nanos() as u64));
+    let _ = options.set_first_byte_timeout(Some(TIMEOUT.as_nanos() as u64));
+
+    let response = outgoing_handler::handle(request, Some(options)).map_err(|err| failed("sending", &err))?;
+    response.subscribe().block();
+    let status = match response.get() {
+      Some(Ok(Ok(response))) => response.status(),
+      Some(Ok(Err(err))) => return Err(failed("waiting for response", &err)),
+      other => return Err(failed("waiting for response", &other.map(|_| ()))),
+    };
+    if !(200..300).contains(&status) {
+      return Err(format!("post to {url} got status {status}").into())
+    }
+    Ok(())
+  }
+
+  fn pause(&mut self, how_long: Duration) {
+    crate::bindings::wasi::clocks::monotonic_clock::subscribe_duration(how_long.as_nanos() as u64).block();
+  }
+
+  fn now(&mut self) -> Duration {
+    Duration::from_nanos(crate::bindings::wasi::clocks::monotonic_clock::now())
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  /// Stands in for a service, and fails the first few posts. Each post takes
+  /// took, on a clock that only moves when the stub says.
+  #[derive(Default)]
+  struct Stub {
+    failures: usize,
+    posts: Vec<(String,Value)>,
+    pauses: Vec<Duration>,
+    took: Duration,
+    clock: Duration,
+  }
+
+  impl Sender for Stub {
+    fn post(&mut self, url: &str, body: &str) -> Result<(), DingString> {
+      self.clock += self.took;
+      if self.failures > 0 {
+        self.failures -= 1;
+        return Err(format!("{url} is down").into())
+      }
+      self.posts.push((url.to_string(), serde_json::from_str(body).unwrap()));
+      Ok(())
+    }
+
+    fn pause(&mut self, how_long: Duration) {
+      self.clock += how_long;
+      self.pauses.push(how_long);
+    }
+
+    fn now(&mut self) -> Duration {
+      self.clock
+    }
+  }
+
+  #[test]
+  fn posting() {
+    let mut db = LeafPaths::new();
+    db.addtree(HOOKS.into(), r#"{
+      "deploys": {"prefix": "config", "url": "http://localhost:8080/deploys"},
+      "everything": {"prefix": "", "url": "http://localhost:8080/all"},
+      "broken": {"prefix": "config"}
+    }"#.into()).unwrap();
+    assert_eq!(hooks(&db).iter().map(|hook| hook.name.as_str()).collect::<Vec<_>>(), vec!["deploys", "everything"]);
+
+    let since = db.revision();
+    db.add("config/replicas".into(), "3".into());
+    db.delete("config/replicas".into());
+    let sent = posts("default", &db, since);
+    assert_eq!(sent.len(), 2);
+
+    let mut stub = Stub { failures: 2, ..Default::default() };
+    for post in &sent {
+      deliver(&mut stub, &post.url, &post.body, BUDGET).unwrap();
+    }
+    assert_eq!(stub.pauses, vec![BACKOFF, BACKOFF * 2]);
+    assert_eq!(stub.posts[0], ("http://localhost:8080/deploys".to_string(), json!({
+      "store": "default",
+      "hook": "deploys",
+      "truncated": false,
+      "changes": [
+        {"seq": since + 1, "path": "config/replicas", "value": "3"},
+        {"seq": since + 2, "path": "config/replicas", "deleted": true},
+      ],
+    })));
+    assert_eq!(stub.posts[1].0, "http://localhost:8080/all");
+    assert_eq!(sent[1].hook, "everything");
+
+    // nothing under config
+    let since = db.revision();
+    db.add("other".into(), "x".into());
+    assert_eq!(posts("default", &db, since).len(), 1);
+    assert_eq!(posts("default", &db, db.revision()), vec![]);
+
+    // more than the feed could keep
+    *db.feed_log_mut() = crate::feed::Feed::new(2);
+    let since = db.revision();
+    db.addtree("config".into(), r#"{"a": 1, "b": 2, "c": 3}"#.into()).unwrap();
+    let body: Value = serde_json::from_str(&posts("default", &db, since)[0].body).unwrap();
+    assert_eq!(body["truncated"], json!(true));
+    assert_eq!(body["changes"].as_array().unwrap().len(), 2);
+
+    // gives up
+    let mut stub = Stub { failures: ATTEMPTS, ..Default::default() };
+    assert_eq!(deliver(&mut stub, "http://localhost:8080", "{}", BUDGET).unwrap_err().to_string(), "http://localhost:8080 is down");
+    assert_eq!(stub.pauses, vec![BACKOFF, BACKOFF * 2]);
+    // or sooner, with no time left to try again
+    let mut stub = Stub { failures: ATTEMPTS, ..Default::default() };
+    assert!(deliver(&mut stub, "http://localhost:8080", "{}", BACKOFF * 2).is_err());
+    assert_eq!(stub.pauses, vec![BACKOFF]);
+  }
+
+  #[test]
+  fn queued() {
+    let post = |hook: &str| Post { hook: hook.into(), url: format!("http://localhost:8080/{hook}"), body: "{}".into() };
+    let mut queue = ["a", "b", "c", "d"].map(post).into_iter().collect::<VecDeque<_>>();
+
+    // a dead service uses up BUDGET, and what's left waits for next time
+    let mut stub = Stub { failures: 100, took: Duration::from_secs(5), ..Default::default() };
+    let tried = deliver_queued(&mut stub, &mut queue);
+    assert_eq!(tried.iter().map(|(hook,rv)| (hook.as_str(), rv.is_ok())).collect::<Vec<_>>(), vec![("a", false), ("b", false)]);
+    assert_eq!(stub.posts.len(), 0);
+    assert!(stub.clock < BUDGET + TIMEOUT, "{:?}", stub.clock);
+    assert_eq!(queue.iter().map(|post| post.hook.as_str()).collect::<Vec<_>>(), vec!["c", "d"]);
+
+    // and once it's back, the rest go
+    let mut stub = Stub::default();
+    assert_eq!(deliver_queued(&mut stub, &mut queue).len(), 2);
+    assert_eq!(stub.posts.iter().map(|(url,_)| url.as_str()).collect::<Vec<_>>(), vec!["http://localhost:8080/c", "http://localhost:8080/d"]);
+    assert!(queue.is_empty());
+  }
+
+  #[test]
+  fn urls() {
+    let split = |url| split_url(url).map(|(https,authority,path)| (https, authority.to_string(), path));
+    assert_eq!(split("http://host").unwrap(), (false, "host".into(), "/".into()));
+    assert_eq!(split("https://host:8443/a/b").unwrap(), (true, "host:8443".into(), "/a/b".into()));
+    assert_eq!(split("http://host?x=1").unwrap(), (false, "host".into(), "/?x=1".into()));
+    assert_eq!(split("http://host/a?x=1&y=/z").unwrap(), (false, "host".into(), "/a?x=1&y=/z".into()));
+    assert_eq!(split("http://user@host#top").unwrap(), (false, "user@host".into(), "/".into()));
+    assert_eq!(split("http://host/a#b/c?d").unwrap(), (false, "host".into(), "/a".into()));
+
+    assert_eq!(split("ftp://host").unwrap_err().to_string(), "ftp://host is not an http or https url");
+    assert_eq!(split("host/a").unwrap_err().to_string(), "host/a is not an http or https url");
+    assert_eq!(split("http:///a").unwrap_err().to_string(), "http:///a has no host");
+  }
+}
@@ -0,0 +1,207 @@
+// Secondary indexes over the leaf values. LeafPaths keeps these up to date on
+// every insert and remove, so they never have to be rebuilt.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Bound;
+
+use crate::tree::{Leaf, PathPattern, SchemaPath};
+
+/// Secondary index from values to the parent paths of leaves matching a
+/// pattern. eg with pattern users/*/email, looking up an email address gives
+/// users/3.
+///
+/// Values are indexed by their text, as returned from get.
+pub struct ValueIndex {
+  pattern: PathPattern,
+  // Keyed by (value,parent) rather than a map of value to set of parents,
+  // so that a lookup is a single range. Counts the matching leaves, since
+  // with a pattern ending in * siblings can have the same value.
+  entries: BTreeMap<(String,SchemaPath),usize>,
+}
+
+impl ValueIndex {
+  pub fn new<'a>(pattern: PathPattern, leaves: impl Iterator<Item=(&'a SchemaPath,&'a Leaf<String>)>) -> Self {
+    let mut index = Self { pattern, entries: BTreeMap::new() };
+    for (path,leaf) in leaves {
+      index.add(path, leaf);
+    }
+    index
+  }
+
+  fn add(&mut self, path : &SchemaPath, leaf : &Leaf<String>) {
+    *self.entries.entry((leaf.to_string(), path.parent())).or_default() += 1;
+  }
+
+  fn remove(&mut self, path : &SchemaPath, leaf : &Leaf<String>) {
+    let key = (leaf.to_string(), path.parent());
+    if let Some(count) = self.entries.get_mut(&key) {
+      *count -= 1;
+      if *count == 0 { self.entries.remove(&key); }
+    }
+  }
+
+  pub fn lookup<'a>(&'a self, value : &'a str) -> impl Iterator<Item=&'a SchemaPath> {
+    let lower = (value.to_string(), SchemaPath::from(vec![]));
+    self.entries
+      .range((Bound::Included(lower), Bound::Unbounded))
+      .take_while(move |((indexed_value,_),_)| indexed_value == value)
+      .map(|((_,parent),_)| parent)
+  }
+}
+
+/// A json number as an f64, ordered by total_cmp so it can be a key.
+#[derive(Debug, Clone, Copy)]
+pub struct NumberKey(f64);
+
+impl NumberKey {
+  /// total_cmp puts -0 before 0, which a scan comparing numbers wouldn't, so
+  /// they're both 0 here.
+  pub fn new(number: f64) -> Self {
+    Self(if number == 0.0 { 0.0 } else { number })
+  }
+}
+
+impl PartialEq for NumberKey {
+  fn eq(&self, other: &Self) -> bool {
+    self.cmp(other) == std::cmp::Ordering::Equal
+  }
+}
+
+impl Eq for NumberKey {}
+
+impl PartialOrd for NumberKey {
+  fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for NumberKey {
+  fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+    self.0.total_cmp(&other.0)
+  }
+}
+
+/// Ordered index of the Leaf::Number values at paths matching a pattern, so
+/// that a numeric range query doesn't have to look at every leaf.
+pub struct NumberIndex {
+  pattern: PathPattern,
+  entries: BTreeMap<NumberKey,BTreeSet<SchemaPath>>,
+}
+
+impl NumberIndex {
+  pub fn new<'a>(pattern: PathPattern, leaves: impl Iterator<Item=(&'a SchemaPath,&'a Leaf<String>)>) -> Self {
+    let mut index = Self { pattern, entries: BTreeMap::new() };
+    for (path,leaf) in leaves {
+      index.add(path, leaf);
+    }
+    index
+  }
+
+  fn add(&mut self, path : &SchemaPath, leaf : &Leaf<String>) {
+    if let Some(number) = leaf.as_f64() {
+      self.entries.entry(NumberKey::new(number)).or_default().insert(path.clone());
+    }
+  }
+
+  fn remove(&mut self, path : &SchemaPath, leaf : &Leaf<String>) {
+    let Some(number) = leaf.as_f64() else { return };
+    if let Some(paths) = self.entries.get_mut(&NumberKey::new(number)) {
+      paths.remove(path);
+      if paths.is_empty() { self.entries.remove(&NumberKey::new(number)); }
+    }
+  }
+
+  /// Paths of the numbers between lower and upper, in number order.
+  pub fn range(&self, lower: Bound<f64>, upper: Bound<f64>) -> impl Iterator<Item=&SchemaPath> {
+    use Bound::*;
+
+    let lower = lower.map(NumberKey::new);
+    let upper = upper.map(NumberKey::new);
+    // BTreeMap::range panics on these, and there's nothing in them anyway.
+    let nothing = match (&lower,&upper) {
+      (Included(l) | Excluded(l), Included(u) | Excluded(u)) if l > u => true,
+      (Excluded(l), Excluded(u)) => l == u,
+      _ => false,
+    };
+
+    (!nothing)
+      .then(|| self.entries.range((lower,upper)))
+      .into_iter()
+      .flatten()
+      .flat_map(|(_,paths)| paths)
+  }
+}
+
+/// Which kind an index is, so it can be made again eg from a dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IndexKind {
+  Value,
+  Number,
+}
+
+/// All the secondary indexes, by name. Value and number indexes share names,
+/// so dropping a name drops whichever it is.
+#[derive(Default)]
+pub struct Indexes {
+  values: BTreeMap<String,ValueIndex>,
+  numbers: BTreeMap<String,NumberIndex>,
+}
+
+impl Indexes {
+  /// Keep every index in step with a change at path. None for old means
+  /// there was nothing there; None for new means it was removed.
+  pub fn update(&mut self, path : &SchemaPath, old : Option<&Leaf<String>>, new : Option<&Leaf<String>>) {
+    for index in self.values.values_mut() {
+      if !index.pattern.matches(path.steps()) { continue }
+      if let Some(old) = old { index.remove(path, old) }
+      if let Some(new) = new { index.add(path, new) }
+    }
+
+    for index in self.numbers.values_mut() {
+      if !index.pattern.matches(path.steps()) { continue }
+      if let Some(old) = old { index.remove(path, old) }
+      if let Some(new) = new { index.add(path, new) }
+    }
+  }
+
+  /// Empty all indexes, but keep them around.
+  pub fn clear(&mut self) {
+    for index in self.values.values_mut() {
+      index.entries.clear();
+    }
+    for index in self.numbers.values_mut() {
+      index.entries.clear();
+    }
+  }
+
+  pub fn insert_value_index(&mut self, name : String, index : ValueIndex) {
+    self.numbers.remove(&name);
+    self.values.insert(name, index);
+  }
+
+  pub fn insert_number_index(&mut self, name : String, index : NumberIndex) {
+    self.values.remove(&name);
+    self.numbers.insert(name, index);
+  }
+
+  pub fn remove(&mut self, name : &str) {
+    self.values.remove(name);
+    self.numbers.remove(name);
+  }
+
+  pub fn value_index(&self, name : &str) -> Option<&ValueIndex> {
+    self.values.get(name)
+  }
+
+  /// Name, kind and pattern of every index. Enough to make them all again.
+  pub fn definitions(&self) -> impl Iterator<Item=(&str,IndexKind,&PathPattern)> {
+    let values = self.values.iter().map(|(name,index)| (name.as_str(), IndexKind::Value, &index.pattern));
+    let numbers = self.numbers.iter().map(|(name,index)| (name.as_str(), IndexKind::Number, &index.pattern));
+    values.chain(numbers)
+  }
+
+  /// A number index over exactly this pattern, if there is one.
+  pub fn number_index_for(&self, pattern : &PathPattern) -> Option<&NumberIndex> {
+    self.numbers.values().find(|index| &index.pattern == pattern)
+  }
+}
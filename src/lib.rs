@@ -5,55 +5,912 @@
 
 use std::cell::RefCell;
 
+mod acl;
+mod diff;
+mod dump;
+mod feed;
+mod history;
+mod hooks;
+mod index;
+mod schemas;
+mod snapshot;
+mod stores;
 mod tree;
+mod txn;
+mod undo;
+mod view;
+mod watch;
 // generated by cargo component build
 mod bindings;
 
-use crate::tree::LeafPaths;
+use crate::acl::Permission;
+use crate::bindings::exports::golem::component::admin::{self, StoreStats};
+use crate::bindings::exports::golem::component::types::{
+    self, AclEntry, Aggregation, Bound, Matcher, Node, NodeValue, Step,
+};
+use crate::bindings::exports::golem::component::{data, query};
+use crate::bindings::golem::api::host;
+use crate::stores::Store;
+use crate::tree::{Collector, DingString, FlatNode, FlatValue, Leaf, PathPattern, SchemaPath};
 
 thread_local! {
     /// This holds the state of our application.
-    static STATE: RefCell<tree::LeafPaths> = RefCell::new(tree::LeafPaths::new());
+    static STATE: RefCell<stores::Stores> = RefCell::new(stores::Stores::default());
+
+    /// watches waiting for changes
+    static WATCHERS: RefCell<watch::Watchers<watch::Promise>> = RefCell::new(watch::Watchers::default());
+}
+
+/// Run f against the named store. Fails if there is no such store, or if f
+/// fails.
+fn with_store<T>(store: &str, f: impl FnOnce(&Store) -> Result<T, DingString>) -> Result<T, String> {
+    STATE.with_borrow(|stores| stores.get(store).and_then(f).map_err(|st| st.to_string()))
+}
+
+/// Same as with_store, for a resource made on the store with id. Fails if
+/// that store has been dropped since, even if there's a new one with the
+/// same name.
+fn with_made<T>(store: &str, id: u64, f: impl FnOnce(&Store) -> Result<T, DingString>) -> Result<T, String> {
+    STATE.with_borrow(|stores| stores.get_made(store, id).and_then(f).map_err(|st| st.to_string()))
+}
+
+/// Run f against the named store, then end any watches and queue posts to any
+/// hooks it changed something for. Fails if there is no such store, or if f fails.
+fn with_store_mut<T>(
+    store: &str,
+    f: impl FnOnce(&mut Store) -> Result<T, DingString>,
+) -> Result<T, String> {
+    change_store(store, None, f)
+}
+
+/// with_store_mut for a resource, as with_made.
+fn with_made_mut<T>(
+    store: &str,
+    id: u64,
+    f: impl FnOnce(&mut Store) -> Result<T, DingString>,
+) -> Result<T, String> {
+    change_store(store, Some(id), f)
+}
+
+fn change_store<T>(
+    store: &str,
+    id: Option<u64>,
+    f: impl FnOnce(&mut Store) -> Result<T, DingString>,
+) -> Result<T, String> {
+    STATE.with_borrow_mut(|stores| {
+        let st = match id {
+            Some(id) => stores.get_made_mut(store, id),
+            None => stores.get_mut(store),
+        }
+        .map_err(|st| st.to_string())?;
+        let since = st.db.revision();
+        let rv = f(st);
+        wake(WATCHERS.with_borrow_mut(|watchers| watchers.ready(store, &st.db)));
+        // deliverhooks posts them, so the write doesn't wait on anyone
+        st.queue(hooks::posts(store, &st.db, since));
+        rv.map_err(|st| st.to_string())
+    })
+}
+
+fn wake(promises: Vec<watch::Promise>) {
+    for promise in promises {
+        host::complete_promise(&promise.into(), &[]);
+    }
+}
+
+impl From<host::PromiseId> for watch::Promise {
+    fn from(promise: host::PromiseId) -> Self {
+        let uuid = promise.worker_id.component_id.uuid;
+        watch::Promise {
+            component: (uuid.high_bits, uuid.low_bits),
+            worker: promise.worker_id.worker_name,
+            oplog: promise.oplog_idx,
+        }
+    }
+}
+
+impl From<watch::Promise> for host::PromiseId {
+    fn from(promise: watch::Promise) -> Self {
+        let (high_bits, low_bits) = promise.component;
+        host::PromiseId {
+            worker_id: host::WorkerId {
+                component_id: host::ComponentId {
+                    uuid: host::Uuid { high_bits, low_bits },
+                },
+                worker_name: promise.worker,
+            },
+            oplog_idx: promise.oplog,
+        }
+    }
 }
 
 struct Component;
 
-impl crate::bindings::exports::golem::component::cli::Guest for Component {
-    fn add(path: String, leaf: String) {
-        STATE.with_borrow_mut(|state| state.add(path, leaf));
+struct Cursor {
+    principal: String,
+    store: String,
+    store_id: u64,
+    prefix: String,
+    cursor: RefCell<tree::ScanCursor>,
+}
+
+impl query::GuestCursor for Cursor {
+    fn next(&self, n: u32) -> Result<Vec<(String, types::Leaf)>, String> {
+        // check every time, in case the grant has gone since scan
+        with_made(&self.store, self.store_id, |st| {
+            let db = st.checked(&self.principal, self.prefix.as_str(), Permission::Read)?;
+            Ok(self
+                .cursor
+                .borrow_mut()
+                .next(db, n as usize)
+                .into_iter()
+                .map(|(path, leaf)| (path, leaf.into()))
+                .collect())
+        })
     }
+}
+
+struct View {
+    principal: String,
+    store: String,
+    store_id: u64,
+    view: view::ReadView,
+}
 
-    fn get(path: String) -> Option<String> {
-        STATE.with_borrow(|state| state.get(path))
+impl query::GuestView for View {
+    fn revision(&self) -> u64 {
+        self.view.revision()
     }
 
-    fn listpaths() -> Vec<String> {
-        STATE.with_borrow(LeafPaths::listpaths)
+    fn get(&self, path: String) -> Result<Option<String>, String> {
+        // the acl as it is now, not as it was when the view was made
+        with_made(&self.store, self.store_id, |st| {
+            st.acl.check(&self.principal, &path.as_str().into(), Permission::Read)?;
+            Ok(self.view.get(path))
+        })
     }
 
-    fn addtree(path: String, json: String) -> Result<(), String> {
-        let rv = STATE.with_borrow_mut(|db| db.addtree(path, json.clone()));
-        rv.map_err(|st| st.to_string())
+    fn gettree(&self, path: String, maxdepth: Option<u32>) -> Result<Option<types::Subtree>, String> {
+        with_made(&self.store, self.store_id, |st| {
+            st.acl.check(&self.principal, &path.as_str().into(), Permission::Read)?;
+            let subtree = self.view.gettree(path, maxdepth.map(|depth| depth as usize));
+            // Same hack as gettree
+            if subtree == Collector::Empty {
+                Ok(None)
+            } else {
+                Ok(Some(types::Subtree::from(&subtree)))
+            }
+        })
+    }
+
+    fn listpaths(&self) -> Result<Vec<String>, String> {
+        with_made(&self.store, self.store_id, |st| Ok(readable(st, &self.principal, self.view.listpaths())))
+    }
+
+    fn diff(&self, path: String) -> Result<Vec<query::Difference>, String> {
+        with_made(&self.store, self.store_id, |st| {
+            st.acl.check(&self.principal, &path.as_str().into(), Permission::Read)?;
+            Ok(self.view.diff(path, &st.db).into_iter().map(query::Difference::from).collect())
+        })
+    }
+}
+
+impl From<diff::Difference> for query::Difference {
+    fn from(difference: diff::Difference) -> Self {
+        query::Difference {
+            path: difference.path.to_string(),
+            before: difference.before.map(types::Leaf::from),
+            after: difference.after.map(types::Leaf::from),
+        }
+    }
+}
+
+// Needs read on both a and b.
+fn checked_diff(principal: &str, store: &str, a: String, b: String) -> Result<Vec<diff::Difference>, String> {
+    with_store(store, |st| {
+        st.checked(principal, a.as_str(), Permission::Read)?;
+        Ok(st.checked(principal, b.as_str(), Permission::Read)?.diff(a, b))
+    })
+}
+
+impl From<Bound> for std::ops::Bound<f64> {
+    fn from(bound: Bound) -> Self {
+        match bound {
+            Bound::Unbounded => std::ops::Bound::Unbounded,
+            Bound::Included(v) => std::ops::Bound::Included(v),
+            Bound::Excluded(v) => std::ops::Bound::Excluded(v),
+        }
     }
+}
+
+impl From<types::Permission> for Permission {
+    fn from(permission: types::Permission) -> Self {
+        match permission {
+            types::Permission::Read => Permission::Read,
+            types::Permission::Write => Permission::Write,
+            types::Permission::Admin => Permission::Admin,
+        }
+    }
+}
 
-    fn gettree(path: String) -> Option<String> {
-        STATE.with_borrow(|state| {
-            let subtree = state.gettree(path);
+impl From<Permission> for types::Permission {
+    fn from(permission: Permission) -> Self {
+        match permission {
+            Permission::Read => types::Permission::Read,
+            Permission::Write => types::Permission::Write,
+            Permission::Admin => types::Permission::Admin,
+        }
+    }
+}
+
+impl From<Aggregation> for tree::Aggregation {
+    fn from(op: Aggregation) -> Self {
+        match op {
+            Aggregation::Sum => tree::Aggregation::Sum,
+            Aggregation::Min => tree::Aggregation::Min,
+            Aggregation::Max => tree::Aggregation::Max,
+            Aggregation::Avg => tree::Aggregation::Avg,
+            Aggregation::Count => tree::Aggregation::Count,
+        }
+    }
+}
+
+impl From<FlatNode> for Node {
+    fn from(node: FlatNode) -> Self {
+        let step = node.step.map(|step| match step {
+            tree::Step::Key(k) => Step::Key(k),
+            tree::Step::Index(i) => Step::Index(i as u32),
+        });
+        let value = match node.value {
+            FlatValue::Null => NodeValue::Null,
+            FlatValue::Bool(v) => NodeValue::Boolean(v),
+            FlatValue::Number(v) => NodeValue::Num(v),
+            FlatValue::String(v) => NodeValue::Str(v),
+            FlatValue::Array => NodeValue::Array,
+            FlatValue::Object => NodeValue::Object,
+            FlatValue::Truncated(n) => NodeValue::Truncated(n as u32),
+        };
+        Node {
+            parent: node.parent.map(|i| i as u32),
+            step,
+            value,
+        }
+    }
+}
+
+impl From<&Collector> for types::Subtree {
+    fn from(subtree: &Collector) -> Self {
+        types::Subtree {
+            json: subtree.to_json().to_string(),
+            truncated: subtree
+                .truncated()
+                .into_iter()
+                .map(|(path, n)| (path.to_string(), n as u32))
+                .collect(),
+        }
+    }
+}
+
+impl From<Node> for FlatNode {
+    fn from(node: Node) -> Self {
+        let step = node.step.map(|step| match step {
+            Step::Key(k) => tree::Step::Key(k),
+            Step::Index(i) => tree::Step::Index(i as usize),
+        });
+        let value = match node.value {
+            NodeValue::Null => FlatValue::Null,
+            NodeValue::Boolean(v) => FlatValue::Bool(v),
+            NodeValue::Num(v) => FlatValue::Number(v),
+            NodeValue::Str(v) => FlatValue::String(v),
+            NodeValue::Array => FlatValue::Array,
+            NodeValue::Object => FlatValue::Object,
+            NodeValue::Truncated(n) => FlatValue::Truncated(n as usize),
+        };
+        FlatNode {
+            parent: node.parent.map(|i| i as usize),
+            step,
+            value,
+        }
+    }
+}
+
+impl TryFrom<Matcher> for tree::Matcher {
+    type Error = DingString;
+
+    fn try_from(matcher: Matcher) -> Result<Self, Self::Error> {
+        match matcher {
+            Matcher::Exact(v) => Ok(tree::Matcher::Exact(v)),
+            Matcher::Prefix(v) => Ok(tree::Matcher::Prefix(v)),
+            Matcher::Substring(v) => Ok(tree::Matcher::Substring(v)),
+            Matcher::Regex(v) => tree::Matcher::regex(&v),
+        }
+    }
+}
+
+impl From<Leaf<String>> for types::Leaf {
+    fn from(leaf: Leaf<String>) -> Self {
+        match leaf {
+            Leaf::String(v) => types::Leaf::Str(v),
+            Leaf::Number(v) => types::Leaf::Num(v),
+            Leaf::Boolean(v) => types::Leaf::Boolean(v),
+            Leaf::Null => types::Leaf::Null,
+        }
+    }
+}
+
+impl From<types::Leaf> for Leaf<String> {
+    fn from(leaf: types::Leaf) -> Self {
+        match leaf {
+            types::Leaf::Str(v) => Leaf::String(v),
+            types::Leaf::Num(v) => Leaf::Number(v),
+            types::Leaf::Boolean(v) => Leaf::Boolean(v),
+            types::Leaf::Null => Leaf::Null,
+        }
+    }
+}
+
+struct Txn {
+    principal: String,
+    store: String,
+    store_id: u64,
+    txn: RefCell<txn::Txn>,
+}
+
+impl data::GuestTxn for Txn {
+    fn get(&self, path: String) -> Result<Option<String>, String> {
+        with_made(&self.store, self.store_id, |st| {
+            let db = st.checked(&self.principal, path.as_str(), Permission::Read)?;
+            Ok(self.txn.borrow_mut().get(db, path))
+        })
+    }
+
+    fn add(&self, path: String, value: String) -> Result<(), String> {
+        with_made(&self.store, self.store_id, |st| {
+            not_root(&path)?;
+            let db = st.checked(&self.principal, path.as_str(), Permission::Write)?;
+            self.txn.borrow_mut().add(db, path, value);
+            Ok(())
+        })
+    }
+
+    fn addtree(&self, path: String, json: String) -> Result<(), String> {
+        with_made(&self.store, self.store_id, |st| {
+            let db = st.checked(&self.principal, path.as_str(), Permission::Write)?;
+            self.txn.borrow_mut().addtree(db, path, json)
+        })
+    }
+
+    fn delete(&self, path: String) -> Result<(), String> {
+        with_made(&self.store, self.store_id, |st| {
+            not_root(&path)?;
+            let db = st.checked(&self.principal, path.as_str(), Permission::Write)?;
+            self.txn.borrow_mut().delete(db, path);
+            Ok(())
+        })
+    }
+
+    fn commit(&self) -> Result<(), String> {
+        with_made_mut(&self.store, self.store_id, |st| {
+            let mut txn = self.txn.borrow_mut();
+            // grants may have changed since the writes went into the txn
+            for path in txn.written() {
+                st.check(&self.principal, path, Permission::Write)?;
+            }
+            st.db.check_schemas(&txn.staged().collect::<Vec<_>>())?;
+            // the whole txn is one step to undo
+            st.db.undoable(|db| txn.commit(db))
+        })
+    }
+
+    fn rollback(&self) -> Result<(), String> {
+        with_made(&self.store, self.store_id, |st| {
+            self.txn.borrow_mut().rollback(&st.db);
+            Ok(())
+        })
+    }
+}
+
+/// Only the paths principal may read.
+fn readable(st: &Store, principal: &str, paths: Vec<String>) -> Vec<String> {
+    paths
+        .into_iter()
+        .filter(|path| st.acl.allows(principal, &path.as_str().into(), Permission::Read))
+        .collect()
+}
+
+/// Fails unless principal can read something in store. Views and txns can
+/// get at the whole store, so each read is checked too.
+fn knows(st: &Store, principal: &str, store: &str) -> Result<(), DingString> {
+    if !st.acl.knows(principal) {
+        return Err(format!("{principal} may not read anything in {store}").into());
+    }
+    Ok(())
+}
+
+/// The root of a store, where drop and indexes need admin.
+fn root() -> SchemaPath {
+    SchemaPath::from(vec![])
+}
+
+/// Fails for the root, which can only have things under it, not a value.
+fn not_root(path: &str) -> Result<(), DingString> {
+    if path.is_empty() {
+        return Err("the root can't have a value, only things under it".to_string().into());
+    }
+    Ok(())
+}
+
+impl data::Guest for Component {
+    type Txn = Txn;
+
+    fn add(principal: String, store: String, path: String, leaf: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            not_root(&path)?;
+            let db = st.checked_mut(&principal, path.as_str(), Permission::Write)?;
+            db.undoable(|db| {
+                db.validated(&path, |db| {
+                    db.add(path.clone(), leaf.clone());
+                    Ok(())
+                })
+            })
+        })
+    }
+
+    fn get(principal: String, store: String, path: String) -> Result<Option<String>, String> {
+        with_store(&store, |st| Ok(st.checked(&principal, path.as_str(), Permission::Read)?.get(path)))
+    }
+
+    fn getvalue(principal: String, store: String, path: String) -> Result<Option<types::Leaf>, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, path.as_str(), Permission::Read)?;
+            Ok(db.getvalue(path).map(types::Leaf::from))
+        })
+    }
+
+    fn setvalue(principal: String, store: String, path: String, leaf: types::Leaf) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            not_root(&path)?;
+            let leaf = Leaf::from(leaf);
+            let db = st.checked_mut(&principal, path.as_str(), Permission::Write)?;
+            db.undoable(|db| db.validated(&path, |db| db.setvalue(path.clone(), leaf.clone())))
+        })
+    }
+
+    fn addtree(principal: String, store: String, path: String, json: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            let db = st.checked_mut(&principal, path.as_str(), Permission::Write)?;
+            db.undoable(|db| db.validated(&path, |db| db.addtree(path.clone(), json.clone())))
+        })
+    }
+
+    fn gettree(
+        principal: String,
+        store: String,
+        path: String,
+        maxdepth: Option<u32>,
+    ) -> Result<Option<types::Subtree>, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, path.as_str(), Permission::Read)?;
+            let subtree = db.gettree(path, maxdepth.map(|depth| depth as usize));
             // TODO This is a hack and not entirely correct. There could be exactly one
             // value at `path`, and it could indeed by null.
             if subtree == tree::Collector::Empty {
-                None
+                Ok(None)
             } else {
-                Some(subtree.to_json().to_string())
+                Ok(Some(types::Subtree::from(&subtree)))
+            }
+        })
+    }
+
+    fn puttree(principal: String, store: String, path: String, tree: Vec<Node>) -> Result<(), String> {
+        let nodes = tree.into_iter().map(FlatNode::from).collect::<Vec<_>>();
+        let tree = Collector::unflatten(&nodes).map_err(|st| st.to_string())?;
+        with_store_mut(&store, |st| {
+            let db = st.checked_mut(&principal, path.as_str(), Permission::Write)?;
+            db.undoable(|db| db.validated(&path, |db| db.puttree(path.clone(), tree.clone())))
+        })
+    }
+
+    fn fetchtree(
+        principal: String,
+        store: String,
+        path: String,
+        maxdepth: Option<u32>,
+    ) -> Result<Option<Vec<Node>>, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, path.as_str(), Permission::Read)?;
+            let subtree = db.gettree(path, maxdepth.map(|depth| depth as usize));
+            // Same hack as gettree
+            if subtree == Collector::Empty {
+                Ok(None)
+            } else {
+                Ok(Some(subtree.flatten().into_iter().map(Node::from).collect()))
+            }
+        })
+    }
+
+    fn history(principal: String, store: String, path: String, limit: u32) -> Result<Vec<data::Change>, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, path.as_str(), Permission::Read)?;
+            Ok(db
+                .history(path, limit as usize)
+                .into_iter()
+                .map(|(at, leaf)| data::Change {
+                    at,
+                    value: leaf.map(types::Leaf::from),
+                })
+                .collect())
+        })
+    }
+
+    fn getat(principal: String, store: String, path: String, at: u64) -> Result<Option<types::Leaf>, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, path.as_str(), Permission::Read)?;
+            Ok(db.getat(path, at)?.map(types::Leaf::from))
+        })
+    }
+
+    fn exists(principal: String, store: String, path: String) -> Result<bool, String> {
+        with_store(&store, |st| Ok(st.checked(&principal, path.as_str(), Permission::Read)?.exists(path)))
+    }
+
+    fn delete(principal: String, store: String, path: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            not_root(&path)?;
+            let db = st.checked_mut(&principal, path.as_str(), Permission::Write)?;
+            db.undoable(|db| {
+                db.validated(&path, |db| {
+                    db.delete(path.clone());
+                    Ok(())
+                })
+            })
+        })
+    }
+
+    fn deletetree(principal: String, store: String, path: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            let db = st.checked_mut(&principal, path.as_str(), Permission::Write)?;
+            db.undoable(|db| {
+                db.validated(&path, |db| {
+                    db.deletetree(path.clone());
+                    Ok(())
+                })
+            })
+        })
+    }
+
+    fn undo(principal: String, store: String, n: u32) -> Result<u32, String> {
+        with_store_mut(&store, |st| {
+            for path in st.db.undo_paths(n as usize) {
+                st.check(&principal, path, Permission::Write)?;
             }
+            st.db.check_schemas(&st.db.undo_changes(n as usize))?;
+            Ok(st.db.undo(n as usize) as u32)
+        })
+    }
+
+    fn begin(principal: String, store: String, detect_conflicts: bool) -> Result<data::Txn, String> {
+        let (txn, store_id) = with_store(&store, |st| {
+            knows(st, &principal, &store)?;
+            Ok((txn::Txn::begin(&st.db, detect_conflicts), st.id()))
+        })?;
+        let txn = Txn {
+            principal,
+            store,
+            store_id,
+            txn: RefCell::new(txn),
+        };
+        Ok(data::Txn::new(txn))
+    }
+}
+
+impl query::Guest for Component {
+    type Cursor = Cursor;
+    type View = View;
+
+    fn openview(principal: String, store: String) -> Result<query::View, String> {
+        let (view, store_id) = with_store(&store, |st| {
+            knows(st, &principal, &store)?;
+            Ok((st.db.view(), st.id()))
+        })?;
+        let view = View {
+            principal,
+            store,
+            store_id,
+            view,
+        };
+        Ok(query::View::new(view))
+    }
+
+    fn listpaths(principal: String, store: String) -> Result<Vec<String>, String> {
+        with_store(&store, |st| Ok(readable(st, &principal, st.db.listpaths())))
+    }
+
+    fn changes(principal: String, store: String, since: u64, prefix: String, limit: u32) -> Result<query::FeedPage, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, prefix.as_str(), Permission::Read)?;
+            let (changes, next) = db.changes(since, prefix, limit as usize)?;
+            let changes = changes
+                .into_iter()
+                .map(|change| query::FeedChange {
+                    seq: change.seq,
+                    path: change.path.to_string(),
+                    value: change.leaf.map(types::Leaf::from),
+                })
+                .collect();
+            Ok(query::FeedPage { changes, next })
+        })
+    }
+
+    fn watch(principal: String, store: String, since: u64, prefix: String, limit: u32) -> Result<query::Watched, String> {
+        let page = Self::changes(principal, store.clone(), since, prefix.clone(), limit)?;
+        if !page.changes.is_empty() {
+            return Ok(query::Watched::Changes(page));
+        }
+        // anything before next is outside prefix
+        let promise = host::create_promise();
+        let ended = WATCHERS.with_borrow_mut(|watchers| {
+            watchers.add(store, prefix.as_str().into(), page.next, promise.clone().into())
+        });
+        wake(ended);
+        Ok(query::Watched::Waiting(query::Waiting {
+            promise,
+            since: page.next,
+        }))
+    }
+
+    fn diff(principal: String, store: String, a: String, b: String) -> Result<Vec<query::Difference>, String> {
+        let differences = checked_diff(&principal, &store, a, b)?;
+        Ok(differences.into_iter().map(query::Difference::from).collect())
+    }
+
+    fn diffpatch(principal: String, store: String, a: String, b: String) -> Result<String, String> {
+        with_store(&store, |st| {
+            st.checked(&principal, a.as_str(), Permission::Read)?;
+            let db = st.checked(&principal, b.as_str(), Permission::Read)?;
+            let patch = diff::to_patch(&diff::subtree_json(db, a), &diff::subtree_json(db, b));
+            Ok(patch.to_string())
+        })
+    }
+
+    fn scan(principal: String, store: String, prefix: String) -> Result<query::Cursor, String> {
+        // fail now rather than at the first next
+        let store_id = with_store(&store, |st| {
+            st.checked(&principal, prefix.as_str(), Permission::Read)?;
+            Ok(st.id())
+        })?;
+        let cursor = Cursor {
+            principal,
+            store,
+            store_id,
+            cursor: RefCell::new(tree::ScanCursor::new(prefix.clone())),
+            prefix,
+        };
+        Ok(query::Cursor::new(cursor))
+    }
+
+    fn listentries(principal: String, store: String, prefix: String) -> Result<Vec<(String, types::Leaf)>, String> {
+        with_store(&store, |st| {
+            Ok(st
+                .checked(&principal, prefix.as_str(), Permission::Read)?
+                .listentries(prefix)
+                .into_iter()
+                .map(|(path, leaf)| (path, leaf.into()))
+                .collect())
+        })
+    }
+
+    fn count(principal: String, store: String, prefix: String) -> Result<u64, String> {
+        with_store(&store, |st| {
+            Ok(st.checked(&principal, prefix.as_str(), Permission::Read)?.count(prefix) as u64)
+        })
+    }
+
+    fn find(
+        principal: String,
+        store: String,
+        matcher: Matcher,
+        prefix: Option<String>,
+    ) -> Result<Vec<String>, String> {
+        let matcher = tree::Matcher::try_from(matcher).map_err(|st| st.to_string())?;
+        with_store(&store, |st| Ok(readable(st, &principal, st.db.find(&matcher, prefix))))
+    }
+
+    fn lookup(principal: String, store: String, index: String, value: String) -> Result<Vec<String>, String> {
+        with_store(&store, |st| Ok(readable(st, &principal, st.db.lookup(index, value)?)))
+    }
+
+    fn numrange(
+        principal: String,
+        store: String,
+        lower: Bound,
+        upper: Bound,
+        pattern: Option<String>,
+    ) -> Result<Vec<String>, String> {
+        with_store(&store, |st| {
+            let paths = st.db.numrange(lower.into(), upper.into(), pattern)?;
+            Ok(readable(st, &principal, paths))
+        })
+    }
+
+    fn aggregate(
+        principal: String,
+        store: String,
+        pattern: String,
+        op: Aggregation,
+    ) -> Result<Option<String>, String> {
+        // can't leave out what principal can't read without changing the
+        // answer, so principal has to be able to read all of it.
+        with_store(&store, |st| {
+            let prefix = PathPattern::from(pattern.as_str()).prefix();
+            let db = st.checked(&principal, prefix, Permission::Read)?;
+            Ok(db.aggregate(pattern, op.into())?.map(|v| v.to_string()))
+        })
+    }
+}
+
+impl admin::Guest for Component {
+    fn createstore(principal: String, store: String) -> Result<(), String> {
+        let rv = STATE.with_borrow_mut(|stores| stores.create(store, principal));
+        rv.map_err(|st| st.to_string())
+    }
+
+    fn liststores(principal: String) -> Vec<String> {
+        STATE.with_borrow(|stores| {
+            stores
+                .list()
+                .into_iter()
+                .filter(|name| stores.get(name).is_ok_and(|st| st.acl.knows(&principal)))
+                .collect()
+        })
+    }
+
+    fn dropstore(principal: String, store: String) -> Result<(), String> {
+        let rv = STATE.with_borrow_mut(|stores| {
+            stores.get(&store)?.acl.check(&principal, &root(), Permission::Admin)?;
+            stores.remove(&store)
+        });
+        // so they fail, rather than waiting for ever
+        wake(WATCHERS.with_borrow_mut(|watchers| watchers.forget(&store)));
+        rv.map_err(|st| st.to_string())
+    }
+
+    fn drop(principal: String, store: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            st.checked_mut(&principal, root(), Permission::Admin)?.undoable(|db| db.clear());
+            Ok(())
+        })
+    }
+
+    fn stats(principal: String, store: String) -> Result<StoreStats, String> {
+        with_store(&store, |st| {
+            let db = st.checked(&principal, root(), Permission::Read)?;
+            Ok(StoreStats {
+                paths: db.paths.len() as u64,
+                revision: db.revision(),
+            })
         })
     }
 
-    fn drop() {
-        STATE.with_borrow_mut(|db| db.0.clear())
+    fn exportstore(principal: String, store: String) -> Result<String, String> {
+        with_store(&store, |st| {
+            st.acl.check(&principal, &root(), Permission::Admin)?;
+            Ok(dump::export(st))
+        })
     }
 
-    fn delete(path: std::string::String) {
-        STATE.with_borrow_mut(|db| db.delete(path))
+    fn importstore(principal: String, store: String, dump: String) -> Result<(), String> {
+        let rv = dump::import(&dump).and_then(|mut st| {
+            // whoever imports it owns it, same as createstore
+            st.acl.grant(principal, root(), Permission::Admin);
+            STATE.with_borrow_mut(|stores| stores.insert(store, st))
+        });
+        rv.map_err(|st| st.to_string())
+    }
+
+    fn grant(
+        principal: String,
+        store: String,
+        grantee: String,
+        prefix: String,
+        permission: types::Permission,
+    ) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            let prefix = SchemaPath::from(prefix);
+            st.acl.check(&principal, &prefix, Permission::Admin)?;
+            st.acl.grant(grantee, prefix, permission.into());
+            Ok(())
+        })
+    }
+
+    fn revoke(principal: String, store: String, grantee: String, prefix: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            let prefix = SchemaPath::from(prefix);
+            st.acl.check(&principal, &prefix, Permission::Admin)?;
+            st.acl.revoke(&grantee, &prefix)
+        })
+    }
+
+    fn listacl(principal: String, store: String) -> Result<Vec<AclEntry>, String> {
+        with_store(&store, |st| {
+            Ok(st
+                .acl
+                .grants()
+                .filter(|(_, prefix, _)| st.acl.allows(&principal, prefix, Permission::Admin))
+                .map(|(grantee, prefix, permission)| AclEntry {
+                    principal: grantee.to_string(),
+                    prefix: prefix.to_string(),
+                    permission: permission.into(),
+                })
+                .collect())
+        })
+    }
+
+    fn deliverhooks(principal: String, store: String) -> Result<u32, String> {
+        with_store_mut(&store, |st| {
+            st.acl.check(&principal, &root(), Permission::Admin)?;
+            Ok(st.deliver(&mut hooks::WasiHttp) as u32)
+        })
+    }
+
+    fn hookfailures(principal: String, store: String) -> Result<Vec<(String, String)>, String> {
+        with_store(&store, |st| {
+            st.acl.check(&principal, &root(), Permission::Admin)?;
+            // not ones that have been removed since
+            let hooks = hooks::hooks(&st.db);
+            Ok(st
+                .failed_hooks
+                .iter()
+                .filter(|(name, _)| hooks.iter().any(|hook| &hook.name == *name))
+                .map(|(name, err)| (name.clone(), err.clone()))
+                .collect())
+        })
+    }
+
+    fn addindex(principal: String, store: String, name: String, pattern: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            st.checked_mut(&principal, root(), Permission::Admin)?.addindex(name, pattern)
+        })
+    }
+
+    fn addnumindex(principal: String, store: String, name: String, pattern: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            st.checked_mut(&principal, root(), Permission::Admin)?.addnumindex(name, pattern)
+        })
+    }
+
+    fn dropindex(principal: String, store: String, name: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            st.checked_mut(&principal, root(), Permission::Admin)?.dropindex(name);
+            Ok(())
+        })
+    }
+
+    fn addschema(principal: String, store: String, name: String, prefix: String, schema: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            st.checked_mut(&principal, root(), Permission::Admin)?.undoable(|db| db.addschema(name, prefix, schema))
+        })
+    }
+
+    fn dropschema(principal: String, store: String, name: String) -> Result<(), String> {
+        with_store_mut(&store, |st| {
+            st.checked_mut(&principal, root(), Permission::Admin)?.undoable(|db| db.dropschema(name));
+            Ok(())
+        })
+    }
+}
+
+impl crate::bindings::exports::golem::api::save_snapshot::Guest for Component {
+    fn save() -> Vec<u8> {
+        STATE.with_borrow(|stores| WATCHERS.with_borrow(|watchers| snapshot::save(stores, watchers)))
+    }
+}
+
+impl crate::bindings::exports::golem::api::load_snapshot::Guest for Component {
+    fn load(bytes: Vec<u8>) -> Result<(), String> {
+        let (stores, watchers) = snapshot::load(&bytes).map_err(|st| st.to_string())?;
+        STATE.set(stores);
+        WATCHERS.set(watchers);
+        Ok(())
     }
 }
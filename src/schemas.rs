@@ -0,0 +1,153 @@
+// JSON Schemas for parts of a store. Like hooks, a store's schemas are kept
+// in the store itself, under $schemas, with the schema as json text:
+//
+//   $schemas/deploys/prefix  config
+//   $schemas/deploys/schema  {"type": "object", "required": ["replicas"]}
+//
+// Whenever there is anything at a schema's prefix, the subtree there has to
+// match the schema. So a write that would leave a subtree that doesn't match
+// is turned down, and the store stays as it was. Only admins can change
+// them, so nobody can loosen a schema that's there to hold them to it.
+
+use serde_json::Value;
+
+use crate::tree::{DingString, LeafPaths, SchemaPath};
+
+/// Where a store keeps its schemas.
+pub const SCHEMAS: &str = "$schemas";
+
+pub struct Schema {
+  pub name: String,
+  pub prefix: SchemaPath,
+  pub schema: String,
+}
+
+impl Schema {
+  /// Fails, saying everything that's wrong, unless the subtree at prefix in
+  /// db is empty or matches.
+  pub fn check(&self, db: &LeafPaths) -> Result<(), DingString> {
+    if let Some(path) = db.mixed(self.prefix.clone()) {
+      return Err(format!("{path} has a value and things under it, so it can't match schema {}", self.name).into())
+    }
+    // gettree has the whole way down from the root
+    let tree = db.gettree(self.prefix.to_string(), None);
+    let Some(subtree) = tree.below(self.prefix.steps()) else { return Ok(()) };
+
+    let validator = compile(&self.schema).map_err(|err| format!("schema {} is broken: {err}", self.name))?;
+    let errors = validator
+      .iter_errors(&subtree.to_json())
+      .map(|err| match err.instance_path.to_string() {
+        at if at.is_empty() => err.to_string(),
+        at => format!("{at}: {err}"),
+      })
+      .collect::<Vec<_>>();
+    if errors.is_empty() { return Ok(()) }
+    Err(format!("{} doesn't match schema {}: {}", self.prefix, self.name, errors.join(", ")).into())
+  }
+}
+
+/// A validator for schema, which is json text. Fails if it isn't json, or
+/// isn't a schema.
+pub fn compile(schema: &str) -> Result<jsonschema::Validator, DingString> {
+  let schema: Value = serde_json::from_str(schema)?;
+  jsonschema::validator_for(&schema).map_err(|err| format!("not a schema: {err}").into())
+}
+
+/// The schemas in db. Ones without both a prefix and a schema are left out.
+pub fn schemas(db: &LeafPaths) -> Vec<Schema> {
+  db.records(SCHEMAS, "schema")
+    .into_iter()
+    .map(|(name,prefix,schema)| Schema { name, prefix, schema })
+    .collect()
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  use crate::tree::Leaf;
+
+  fn store() -> LeafPaths {
+    let mut db = LeafPaths::new();
+    db.addtree("config".into(), r#"{"replicas": 2, "hosts": ["a"]}"#.into()).unwrap();
+    db.addschema("deploys".into(), "config".into(), r#"{
+      "type": "object",
+      "required": ["replicas"],
+      "properties": {
+        "replicas": {"type": "integer", "minimum": 1},
+        "hosts": {"type": "array", "items": {"type": "string"}}
+      }
+    }"#.into()).unwrap();
+    db
+  }
+
+  #[test]
+  fn turned_down() {
+    let mut db = store();
+    let revision = db.revision();
+
+    let err = db.validated("config", |db| db.addtree("config".into(), r#"{"replicas": "three"}"#.into())).unwrap_err();
+    assert_eq!(err.to_string(), r#"config doesn't match schema deploys: /replicas: "three" is not of type "integer""#);
+    db.validated("config/hosts/1", |db| { db.add("config/hosts/1".into(), "b".into()); Ok(()) }).unwrap();
+    let err = db.validated("config/replicas", |db| { db.delete("config/replicas".into()); Ok(()) }).unwrap_err();
+    assert_eq!(err.to_string(), r#"config doesn't match schema deploys: "replicas" is a required property"#);
+    // and from above
+    let err = db.validated("", |db| db.addtree("".into(), r#"{"config": {"replicas": 0}}"#.into())).unwrap_err();
+    assert_eq!(err.to_string(), "config doesn't match schema deploys: /replicas: 0 is less than the minimum of 1");
+
+    // only the one that worked happened
+    assert_eq!(db.revision(), revision + 1);
+    assert_eq!(db.gettree("config".into(), None).to_json(), serde_json::json!({"config": {"replicas": 2, "hosts": ["a", "b"]}}));
+
+    // a value with things under it isn't json, so it can't match
+    let err = db.validated("config", |db| { db.add("config".into(), "x".into()); Ok(()) }).unwrap_err();
+    assert_eq!(err.to_string(), "config has a value and things under it, so it can't match schema deploys");
+    let err = db.validated("config/replicas/0", |db| { db.add("config/replicas/0".into(), "3".into()); Ok(()) }).unwrap_err();
+    assert_eq!(err.to_string(), "config/replicas has a value and things under it, so it can't match schema deploys");
+
+    // f runs once, whether or not there's a schema to check
+    for path in ["config/replicas", "other"] {
+      let runs = std::cell::Cell::new(0);
+      db.validated(path, |db| { runs.set(runs.get() + 1); db.setvalue(path.into(), Leaf::Number("4".into())) }).unwrap();
+      assert_eq!(runs.get(), 1);
+      assert_eq!(db.getvalue(path.into()), Some(Leaf::Number("4".into())));
+    }
+
+    // nothing there is fine
+    db.validated("config", |db| { db.deletetree("config".into()); Ok(()) }).unwrap();
+    db.validated("other", |db| { db.add("other".into(), "x".into()); Ok(()) }).unwrap();
+  }
+
+  #[test]
+  fn undoing() {
+    let mut db = LeafPaths::new();
+    db.undoable(|db| db.addtree("config".into(), r#"{"replicas": "three"}"#.into())).unwrap();
+    db.undoable(|db| db.setvalue("config/replicas".into(), Leaf::Number("3".into()))).unwrap();
+    db.addschema("ints".into(), "config".into(), r#"{"properties": {"replicas": {"type": "integer"}}}"#.into()).unwrap();
+
+    // would put back what was there before the schema
+    let err = db.check_schemas(&db.undo_changes(1)).unwrap_err();
+    assert_eq!(err.to_string(), r#"config doesn't match schema ints: /replicas: "three" is not of type "integer""#);
+    // undoing both leaves nothing there, which is fine
+    db.check_schemas(&db.undo_changes(2)).unwrap();
+  }
+
+  #[test]
+  fn attaching() {
+    let mut db = store();
+    assert_eq!(schemas(&db).iter().map(|schema| schema.name.as_str()).collect::<Vec<_>>(), vec!["deploys"]);
+
+    let err = db.addschema("bad".into(), "config".into(), r#"{"type": "wibble"}"#.into()).unwrap_err();
+    assert!(err.to_string().starts_with("not a schema: "), "{err}");
+    let err = db.addschema("strings".into(), "config".into(), r#"{"type": "string"}"#.into()).unwrap_err();
+    assert_eq!(err.to_string(), r#"config doesn't match schema strings: {"hosts":["a"],"replicas":2} is not of type "string""#);
+    assert_eq!(schemas(&db).len(), 1);
+
+    // broken by hand by an admin, so everything under it is turned down until it's fixed
+    db.add(format!("{SCHEMAS}/deploys/schema"), "{".into());
+    let err = db.validated("config/replicas", |db| { db.add("config/replicas".into(), "3".into()); Ok(()) }).unwrap_err();
+    assert!(err.to_string().starts_with("schema deploys is broken: "), "{err}");
+  }
+}
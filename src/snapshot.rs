@@ -0,0 +1,740 @@
+// Binary snapshot of every store, for golem's save-snapshot and load-snapshot.
+// golem calls save on the old version of the component and load on the new
+// one, so the layout here is fixed, and doesn't follow whatever LeafPaths
+// happens to look like.
+//
+// save only ever writes the current version, but load has to keep on loading
+// every version there has ever been. So each version has its own decoder,
+// which turns the bytes into an Image as that version saw it. Then the
+// migrations bring the Image up to date, one version at a time. To change the
+// format, bump VERSION, add a decoder and a migration, and add a golden file.
+//
+// Every version starts with
+//
+//   magic     b"slkvs\0"
+//   version   u16 little-endian
+//
+// Version 1, with fixed size little-endian numbers. A str is a u32 length
+// then utf-8 bytes.
+//
+//   stores    u32 count, then for each
+//     name      str
+//     revision  u64
+//     acl       u32 count of (principal str, prefix path, permission u8)
+//     indexes   u32 count of (name str, kind u8, pattern str)
+//     entries   u64 count of (path, leaf)
+//
+//   path  u32 count of steps, each tag u8 then 0: key str | 1: index u64
+//   leaf  tag u8 then 0: str str | 1: num str | 2: boolean u8 | 3: null
+//
+// Version 2 has the same layout after a header, except that every number
+// other than a tag or a boolean is a LEB128 varint, which is a lot smaller.
+// The header catches a damaged snapshot, rather than loading it wrong.
+//
+//   length    u64 little-endian, of everything after the header
+//   crc       u32 little-endian, crc32 of everything after the header
+//
+// Version 3 is the same as 2, with the history of each store after its
+// entries.
+//
+//     history   count of (path, forgotten u8, count of changes)
+//     change    at, present u8, then leaf if present
+//
+// Version 4 is the same as 3, with the feed of each store after its history.
+//
+//     feed      since, then count of (seq, path, present u8, then leaf if present)
+//
+// Version 5 is the same as 4, with when the history of deleted paths was
+// last dropped after the history.
+//
+//     gone      at
+//
+// Version 6 is the same as 5, with the watches waiting on each store after
+// its feed, each with its golem promise id.
+//
+//     watchers  count of (prefix path, since, promise)
+//     promise   component uuid high, component uuid low, worker str, oplog index
+//
+// Version 7 is the same as 6, with the hook posts still waiting on each store
+// after its watchers, then why its hooks last failed.
+//
+//     queued    count of (hook str, url str, body str)
+//     failures  count of (hook str, error str)
+
+use crate::acl::{Acl, Permission};
+use crate::feed::FeedChange;
+use crate::history::{Change, PathHistory};
+use crate::hooks::Post;
+use crate::index::IndexKind;
+use crate::stores::{Store, Stores};
+use crate::tree::{DingString, Leaf, LeafPaths, SchemaPath, Step};
+use crate::watch::{Promise, Watchers};
+
+const MAGIC: &[u8] = b"slkvs\0";
+const VERSION: u16 = 7;
+
+/// What's in a store, without any of the ways LeafPaths keeps it.
+#[derive(Debug, Clone, PartialEq)]
+struct StoreImage {
+  name: String,
+  revision: u64,
+  grants: Vec<(String,SchemaPath,Permission)>,
+  indexes: Vec<(String,IndexKind,String)>,
+  entries: Vec<(SchemaPath,Leaf<String>)>,
+  history: Vec<(SchemaPath,PathHistory)>,
+  // history of paths deleted at or before this is gone
+  history_gone: u64,
+  // every change after feed_since is in feed
+  feed_since: u64,
+  feed: Vec<FeedChange>,
+  watchers: Vec<(SchemaPath,u64,Promise)>,
+  queued: Vec<Post>,
+  failed_hooks: Vec<(String,String)>,
+}
+
+type Image = Vec<StoreImage>;
+
+type Decoder = fn(&[u8]) -> Result<Image, DingString>;
+
+/// Decoder for each version, starting at 1.
+const DECODERS: &[Decoder] = &[decode_v1, decode_v2, decode_v3, decode_v4, decode_v5, decode_v6, decode_v7];
+
+/// MIGRATIONS[n] brings an Image from version n+1 up to version n+2.
+const MIGRATIONS: &[fn(Image) -> Image] = &[v1_to_v2, v2_to_v3, v3_to_v4, v4_to_v5, v5_to_v6, v6_to_v7];
+
+// only the encoding changed
+fn v1_to_v2(image: Image) -> Image {
+  image
+}
+
+// There was no history, so nothing is known about what any path had before.
+fn v2_to_v3(image: Image) -> Image {
+  image.into_iter()
+    .map(|store| {
+      let history = store.entries
+        .iter()
+        .map(|(path,_)| (path.clone(), PathHistory { forgotten: true, changes: Default::default() }))
+        .collect();
+      StoreImage { history, ..store }
+    })
+    .collect()
+}
+
+// There was no feed, so it starts from the revision.
+fn v3_to_v4(image: Image) -> Image {
+  image.into_iter()
+    .map(|store| StoreImage { feed_since: store.revision, feed: vec![], ..store })
+    .collect()
+}
+
+// The history of deleted paths was kept for ever, so none has gone.
+fn v4_to_v5(image: Image) -> Image {
+  image
+}
+
+// Watches waited inside the worker, so there were none left to keep.
+fn v5_to_v6(image: Image) -> Image {
+  image
+}
+
+// Hooks were posted to as part of each write, so nothing was waiting, and
+// failures weren't kept.
+fn v6_to_v7(image: Image) -> Image {
+  image
+}
+
+fn image(stores: &Stores, watchers: &Watchers<Promise>) -> Image {
+  stores.iter()
+    .map(|(name,store)| StoreImage {
+      name: name.to_string(),
+      revision: store.db.revision(),
+      grants: store.acl.grants().map(|(principal,prefix,permission)| (principal.to_string(), prefix.clone(), permission)).collect(),
+      indexes: store.db.index_definitions().map(|(name,kind,pattern)| (name.to_string(), kind, pattern.to_string())).collect(),
+      entries: store.db.paths.iter().map(|(path,leaf)| (path.clone(), leaf.clone())).collect(),
+      history: store.db.history_log().paths().map(|(path,history)| (path.clone(), history.clone())).collect(),
+      history_gone: store.db.history_log().gone(),
+      feed_since: store.db.feed_log().since(),
+      feed: store.db.feed_log().changes().cloned().collect(),
+      watchers: watchers.waiting_on(name).map(|(prefix,since,promise)| (prefix.clone(), since, promise.clone())).collect(),
+      queued: store.queued.iter().cloned().collect(),
+      failed_hooks: store.failed_hooks.iter().map(|(hook,err)| (hook.clone(), err.clone())).collect(),
+    })
+    .collect()
+}
+
+fn build(image: Image) -> Result<(Stores,Watchers<Promise>), DingString> {
+  let mut stores = Stores::default();
+  let mut watchers = Watchers::default();
+  for store in image {
+    for (prefix,since,promise) in store.watchers {
+      // it was within the limit when saved, so nothing is ended here
+      watchers.add(store.name.clone(), prefix, since, promise);
+    }
+
+    let mut acl = Acl::default();
+    for (principal,prefix,permission) in store.grants {
+      acl.grant(principal, prefix, permission);
+    }
+
+    let mut db = LeafPaths::new();
+    db.apply(store.entries.into_iter().map(|(path,leaf)| (path, Some(leaf))));
+    for (name,kind,pattern) in store.indexes {
+      match kind {
+        IndexKind::Value => db.addindex(name, pattern)?,
+        IndexKind::Number => db.addnumindex(name, pattern)?,
+      }
+    }
+    // last, because everything above bumps them
+    db.history_log_mut().restore(store.history, store.history_gone);
+    db.set_revision(store.revision);
+    db.feed_log_mut().restore(store.feed_since, store.feed);
+
+    let mut made = Store::new(db, acl);
+    made.queued = store.queued.into();
+    made.failed_hooks = store.failed_hooks.into_iter().collect();
+    stores.insert(store.name, made)?;
+  }
+  Ok((stores, watchers))
+}
+
+/// Standard crc32, as in zip and png.
+fn crc32(bytes: &[u8]) -> u32 {
+  let mut crc = !0u32;
+  for byte in bytes {
+    crc ^= *byte as u32;
+    for _ in 0..8 {
+      crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+    }
+  }
+  !crc
+}
+
+// Only ever writes the current version.
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn u8(&mut self, v: u8) { self.0.push(v) }
+
+  fn varint(&mut self, mut v: u64) {
+    loop {
+      let byte = (v & 0x7f) as u8;
+      v >>= 7;
+      if v == 0 { return self.u8(byte) }
+      self.u8(byte | 0x80);
+    }
+  }
+
+  fn str(&mut self, v: &str) {
+    self.varint(v.len() as u64);
+    self.0.extend_from_slice(v.as_bytes());
+  }
+
+  fn path(&mut self, path: &SchemaPath) {
+    self.varint(path.steps().len() as u64);
+    for step in path.steps() {
+      match step {
+        Step::Key(k) => { self.u8(0); self.str(k) }
+        Step::Index(i) => { self.u8(1); self.varint(*i as u64) }
+      }
+    }
+  }
+
+  fn leaf(&mut self, leaf: &Leaf<String>) {
+    match leaf {
+      Leaf::String(v) => { self.u8(0); self.str(v) }
+      Leaf::Number(v) => { self.u8(1); self.str(v) }
+      Leaf::Boolean(v) => { self.u8(2); self.u8(*v as u8) }
+      Leaf::Null => self.u8(3),
+    }
+  }
+
+  fn store(&mut self, store: &StoreImage) {
+    self.str(&store.name);
+    self.varint(store.revision);
+
+    self.varint(store.grants.len() as u64);
+    for (principal,prefix,permission) in &store.grants {
+      self.str(principal);
+      self.path(prefix);
+      self.u8(match permission {
+        Permission::Read => 0,
+        Permission::Write => 1,
+        Permission::Admin => 2,
+      });
+    }
+
+    self.varint(store.indexes.len() as u64);
+    for (name,kind,pattern) in &store.indexes {
+      self.str(name);
+      self.u8(match kind {
+        IndexKind::Value => 0,
+        IndexKind::Number => 1,
+      });
+      self.str(pattern);
+    }
+
+    self.varint(store.entries.len() as u64);
+    for (path,leaf) in &store.entries {
+      self.path(path);
+      self.leaf(leaf);
+    }
+
+    self.varint(store.history.len() as u64);
+    for (path,history) in &store.history {
+      self.path(path);
+      self.u8(history.forgotten as u8);
+      self.varint(history.changes.len() as u64);
+      for change in &history.changes {
+        self.varint(change.at);
+        match &change.leaf {
+          Some(leaf) => { self.u8(1); self.leaf(leaf) }
+          None => self.u8(0),
+        }
+      }
+    }
+    self.varint(store.history_gone);
+
+    self.varint(store.feed_since);
+    self.varint(store.feed.len() as u64);
+    for change in &store.feed {
+      self.varint(change.seq);
+      self.path(&change.path);
+      match &change.leaf {
+        Some(leaf) => { self.u8(1); self.leaf(leaf) }
+        None => self.u8(0),
+      }
+    }
+
+    self.varint(store.watchers.len() as u64);
+    for (prefix,since,promise) in &store.watchers {
+      self.path(prefix);
+      self.varint(*since);
+      self.varint(promise.component.0);
+      self.varint(promise.component.1);
+      self.str(&promise.worker);
+      self.varint(promise.oplog);
+    }
+
+    self.varint(store.queued.len() as u64);
+    for post in &store.queued {
+      self.str(&post.hook);
+      self.str(&post.url);
+      self.str(&post.body);
+    }
+
+    self.varint(store.failed_hooks.len() as u64);
+    for (hook,err) in &store.failed_hooks {
+      self.str(hook);
+      self.str(err);
+    }
+  }
+}
+
+// Reads the body of any version. They only differ in how numbers are kept,
+// and in what comes after the entries.
+struct Reader<'a> {
+  bytes: &'a [u8],
+  version: u16,
+}
+
+impl Reader<'_> {
+  fn take(&mut self, n: usize) -> Result<&[u8], DingString> {
+    if self.bytes.len() < n {
+      return Err("snapshot is truncated".to_string().into())
+    }
+    let (bytes,rst) = self.bytes.split_at(n);
+    self.bytes = rst;
+    Ok(bytes)
+  }
+
+  fn u8(&mut self) -> Result<u8, DingString> {
+    Ok(self.take(1)?[0])
+  }
+
+  fn varint(&mut self) -> Result<u64, DingString> {
+    let mut v = 0u64;
+    for shift in (0..64).step_by(7) {
+      let byte = self.u8()?;
+      v |= ((byte & 0x7f) as u64) << shift;
+      if byte & 0x80 == 0 { return Ok(v) }
+    }
+    Err("snapshot has a number that's too big".to_string().into())
+  }
+
+  // u32 in version 1
+  fn count(&mut self) -> Result<u64, DingString> {
+    if self.version >= 2 { return self.varint() }
+    Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()) as u64)
+  }
+
+  // u64 in version 1
+  fn u64(&mut self) -> Result<u64, DingString> {
+    if self.version >= 2 { return self.varint() }
+    Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+  }
+
+  fn str(&mut self) -> Result<String, DingString> {
+    let len = self.count()? as usize;
+    let bytes = self.take(len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|err| format!("snapshot has a bad string: {err}").into())
+  }
+
+  fn tag(&mut self, what: &str, max: u8) -> Result<u8, DingString> {
+    let tag = self.u8()?;
+    if tag > max {
+      return Err(format!("snapshot has unknown {what} {tag}").into())
+    }
+    Ok(tag)
+  }
+
+  fn path(&mut self) -> Result<SchemaPath, DingString> {
+    let mut steps = vec![];
+    for _ in 0..self.count()? {
+      let step = match self.tag("step", 1)? {
+        0 => Step::Key(self.str()?),
+        _ => Step::Index(self.u64()? as usize),
+      };
+      steps.push(step);
+    }
+    Ok(steps.into())
+  }
+
+  fn leaf(&mut self) -> Result<Leaf<String>, DingString> {
+    Ok(match self.tag("leaf", 3)? {
+      0 => Leaf::String(self.str()?),
+      1 => Leaf::Number(self.str()?),
+      2 => Leaf::Boolean(self.u8()? != 0),
+      _ => Leaf::Null,
+    })
+  }
+
+  fn store(&mut self) -> Result<StoreImage, DingString> {
+    let name = self.str()?;
+    let revision = self.u64()?;
+
+    let mut grants = vec![];
+    for _ in 0..self.count()? {
+      let principal = self.str()?;
+      let prefix = self.path()?;
+      let permission = match self.tag("permission", 2)? {
+        0 => Permission::Read,
+        1 => Permission::Write,
+        _ => Permission::Admin,
+      };
+      grants.push((principal, prefix, permission));
+    }
+
+    let mut indexes = vec![];
+    for _ in 0..self.count()? {
+      let name = self.str()?;
+      let kind = match self.tag("index kind", 1)? {
+        0 => IndexKind::Value,
+        _ => IndexKind::Number,
+      };
+      indexes.push((name, kind, self.str()?));
+    }
+
+    let mut entries = vec![];
+    for _ in 0..self.u64()? {
+      entries.push((self.path()?, self.leaf()?));
+    }
+
+    let mut history = vec![];
+    if self.version >= 3 {
+      for _ in 0..self.count()? {
+        let path = self.path()?;
+        let forgotten = self.u8()? != 0;
+        let mut changes = std::collections::VecDeque::new();
+        for _ in 0..self.count()? {
+          let at = self.u64()?;
+          let leaf = match self.u8()? {
+            0 => None,
+            _ => Some(self.leaf()?),
+          };
+          changes.push_back(Change { at, leaf });
+        }
+        history.push((path, PathHistory { forgotten, changes }));
+      }
+    }
+
+    let history_gone = if self.version >= 5 { self.u64()? } else { 0 };
+
+    let mut feed_since = 0;
+    let mut feed = vec![];
+    if self.version >= 4 {
+      feed_since = self.u64()?;
+      for _ in 0..self.count()? {
+        let seq = self.u64()?;
+        let path = self.path()?;
+        let leaf = match self.u8()? {
+          0 => None,
+          _ => Some(self.leaf()?),
+        };
+        feed.push(FeedChange { seq, path, leaf });
+      }
+    }
+
+    let mut watchers = vec![];
+    if self.version >= 6 {
+      for _ in 0..self.count()? {
+        let prefix = self.path()?;
+        let since = self.u64()?;
+        let component = (self.u64()?, self.u64()?);
+        let promise = Promise { component, worker: self.str()?, oplog: self.u64()? };
+        watchers.push((prefix, since, promise));
+      }
+    }
+
+    let mut queued = vec![];
+    let mut failed_hooks = vec![];
+    if self.version >= 7 {
+      for _ in 0..self.count()? {
+        queued.push(Post { hook: self.str()?, url: self.str()?, body: self.str()? });
+      }
+      for _ in 0..self.count()? {
+        failed_hooks.push((self.str()?, self.str()?));
+      }
+    }
+
+    Ok(StoreImage {
+      name, revision, grants, indexes, entries, history, history_gone, feed_since, feed, watchers, queued, failed_hooks,
+    })
+  }
+
+  fn image(mut self) -> Result<Image, DingString> {
+    let mut image = vec![];
+    for _ in 0..self.count()? {
+      image.push(self.store()?);
+    }
+    if !self.bytes.is_empty() {
+      return Err(format!("snapshot has {} bytes left over", self.bytes.len()).into())
+    }
+    Ok(image)
+  }
+}
+
+fn decode_v1(bytes: &[u8]) -> Result<Image, DingString> {
+  Reader { bytes, version: 1 }.image()
+}
+
+fn decode_v2(bytes: &[u8]) -> Result<Image, DingString> {
+  decode_checked(bytes, 2)
+}
+
+fn decode_v3(bytes: &[u8]) -> Result<Image, DingString> {
+  decode_checked(bytes, 3)
+}
+
+fn decode_v4(bytes: &[u8]) -> Result<Image, DingString> {
+  decode_checked(bytes, 4)
+}
+
+fn decode_v5(bytes: &[u8]) -> Result<Image, DingString> {
+  decode_checked(bytes, 5)
+}
+
+fn decode_v6(bytes: &[u8]) -> Result<Image, DingString> {
+  decode_checked(bytes, 6)
+}
+
+fn decode_v7(bytes: &[u8]) -> Result<Image, DingString> {
+  decode_checked(bytes, 7)
+}
+
+// version 2 onwards, with a length and crc
+fn decode_checked(bytes: &[u8], version: u16) -> Result<Image, DingString> {
+  let mut header = Reader { bytes, version: 1 };
+  let len = header.u64()?;
+  let crc = u32::from_le_bytes(header.take(4)?.try_into().unwrap());
+  let body = header.bytes;
+
+  match (body.len() as u64).cmp(&len) {
+    std::cmp::Ordering::Less => return Err("snapshot is truncated".to_string().into()),
+    std::cmp::Ordering::Greater => return Err(format!("snapshot has {} bytes left over", body.len() as u64 - len).into()),
+    std::cmp::Ordering::Equal => (),
+  }
+  if crc32(body) != crc {
+    return Err("snapshot is damaged".to_string().into())
+  }
+  Reader { bytes: body, version }.image()
+}
+
+/// Every store, and the watches waiting on them, for load to make again.
+pub fn save(stores: &Stores, watchers: &Watchers<Promise>) -> Vec<u8> {
+  let image = image(stores, watchers);
+  let mut body = Writer(vec![]);
+  body.varint(image.len() as u64);
+  for store in &image {
+    body.store(store);
+  }
+
+  let mut bytes = MAGIC.to_vec();
+  bytes.extend_from_slice(&VERSION.to_le_bytes());
+  bytes.extend_from_slice(&(body.0.len() as u64).to_le_bytes());
+  bytes.extend_from_slice(&crc32(&body.0).to_le_bytes());
+  bytes.extend_from_slice(&body.0);
+  bytes
+}
+
+/// The stores and watches in a snapshot from save, of this version or any
+/// before it.
+pub fn load(bytes: &[u8]) -> Result<(Stores,Watchers<Promise>), DingString> {
+  let Some(rst) = bytes.strip_prefix(MAGIC) else {
+    return Err("not an slkvs snapshot".to_string().into())
+  };
+  let Some((version,body)) = rst.split_first_chunk::<2>() else {
+    return Err("snapshot is truncated".to_string().into())
+  };
+  let version = u16::from_le_bytes(*version);
+
+  // versions start at 1, so version n is at n-1
+  let Some(at) = (version as usize).checked_sub(1).filter(|at| *at < DECODERS.len()) else {
+    return Err(format!("can't load snapshot version {version}, newest is {VERSION}").into())
+  };
+  let mut image = DECODERS[at](body)?;
+  for migrate in &MIGRATIONS[at..] {
+    image = migrate(image);
+  }
+  build(image)
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  // Every version there has been, each saved from stores() by the code of its
+  // time. load has to keep on loading all of them.
+  const GOLDEN: &[(u16, &[u8])] = &[
+    (1, include_bytes!("../golden/snapshot-v1.bin")),
+    (2, include_bytes!("../golden/snapshot-v2.bin")),
+    (3, include_bytes!("../golden/snapshot-v3.bin")),
+    (4, include_bytes!("../golden/snapshot-v4.bin")),
+    (5, include_bytes!("../golden/snapshot-v5.bin")),
+    (6, include_bytes!("../golden/snapshot-v6.bin")),
+    (7, include_bytes!("../golden/snapshot-v7.bin")),
+  ];
+
+  fn stores() -> Stores {
+    let mut stores = Stores::default();
+    stores.create("uno".into(), "ann".into()).unwrap();
+    stores.create("due".into(), "bob".into()).unwrap();
+
+    let uno = stores.get_mut("uno").unwrap();
+    uno.db.set_clock(|| 1_700_000_000_000);
+    uno.db.addtree("".into(), r#"{"0": "key"}"#.into()).unwrap();
+    // addtree would make it the string "true"
+    uno.db.setvalue("users/0/admin".into(), Leaf::Boolean(true)).unwrap();
+    uno.db.addtree("users/0".into(), r#"{"email": "ann@example.com", "boss": null}"#.into()).unwrap();
+    uno.db.setvalue("users/0/age".into(), Leaf::Number("1.50".into())).unwrap();
+    uno.db.addindex("emails".into(), "users/*/email".into()).unwrap();
+    uno.db.addnumindex("ages".into(), "users/**".into()).unwrap();
+    uno.acl.grant("bob".into(), "users/0".into(), Permission::Read);
+    uno.queue(vec![Post { hook: "mail".into(), url: "http://localhost:8080".into(), body: "{}".into() }]);
+    uno.failed_hooks.insert("audit".into(), "http://localhost:8081 is down".into());
+    stores
+  }
+
+  fn watchers() -> Watchers<Promise> {
+    let mut watchers = Watchers::default();
+    let promise = Promise { component: (1, 2), worker: "fst".into(), oplog: 42 };
+    watchers.add("uno".into(), "users".into(), 5, promise);
+    watchers
+  }
+
+  #[test]
+  fn round_trip() {
+    let stores = stores();
+    let bytes = save(&stores, &watchers());
+    let (loaded,watching) = load(&bytes).unwrap();
+    assert_eq!(save(&loaded, &watching), bytes);
+
+    assert_eq!(loaded.list(), vec!["due", "uno"]);
+    let uno = loaded.get("uno").unwrap();
+    assert_eq!(uno.db.revision(), stores.get("uno").unwrap().db.revision());
+    assert_eq!(uno.db.getvalue("users/0/age".into()), Some(Leaf::Number("1.50".into())));
+    assert_eq!(uno.db.getvalue("users/0/admin".into()), Some(Leaf::Boolean(true)));
+    assert_eq!(uno.db.paths.get(&SchemaPath::singleton(Step::Key("0".into()))), Some(&Leaf::String("key".into())));
+    assert_eq!(uno.db.lookup("emails".into(), "ann@example.com".into()).unwrap(), vec!["users/0"]);
+    assert!(uno.acl.allows("bob", &"users/0/email".into(), Permission::Read));
+    assert!(loaded.get("due").unwrap().acl.allows("bob", &"".into(), Permission::Admin));
+    assert_eq!(uno.db.history("users/0/email".into(), 10), vec![(1_700_000_000_000, Some(Leaf::String("ann@example.com".into())))]);
+    let (changes,_) = uno.db.changes(0, "users/0/age".into(), 10).unwrap();
+    assert_eq!(changes.iter().map(|change| change.seq).collect::<Vec<_>>(), vec![5]);
+    let waiting = watching.waiting_on("uno").map(|(prefix,since,promise)| (prefix.to_string(), since, promise.oplog)).collect::<Vec<_>>();
+    assert_eq!(waiting, vec![("users".to_string(), 5, 42)]);
+    assert_eq!(uno.queued.iter().map(|post| post.hook.as_str()).collect::<Vec<_>>(), vec!["mail"]);
+    assert_eq!(uno.failed_hooks["audit"], "http://localhost:8081 is down");
+  }
+
+  #[test]
+  fn golden() {
+    let expected = image(&stores(), &watchers());
+    // what's left after loading from before hook posts were queued
+    let without_hooks = expected.iter().map(|store| StoreImage { queued: vec![], failed_hooks: vec![], ..store.clone() }).collect::<Image>();
+    // and before watches were kept
+    let without_watchers = without_hooks.iter().map(|store| StoreImage { watchers: vec![], ..store.clone() }).collect::<Image>();
+    // and before there was a feed
+    let without_feed = without_watchers.iter().map(|store| StoreImage { feed_since: 0, feed: vec![], ..store.clone() }).collect();
+    let without_feed = v3_to_v4(without_feed);
+    // and before there was history
+    let without_history = without_feed.iter().map(|store| StoreImage { history: vec![], ..store.clone() }).collect();
+    let without_history = v2_to_v3(without_history);
+
+    for (version,bytes) in GOLDEN {
+      let (loaded,watching) = load(bytes).unwrap_or_else(|err| panic!("version {version}: {err}"));
+      let expected = match version {
+        ..3 => &without_history,
+        3 => &without_feed,
+        4 | 5 => &without_watchers,
+        6 => &without_hooks,
+        _ => &expected,
+      };
+      assert_eq!(&image(&loaded, &watching), expected, "version {version}");
+    }
+
+    // save hasn't changed without a new version
+    assert_eq!(DECODERS.len(), GOLDEN.len());
+    assert_eq!(MIGRATIONS.len(), GOLDEN.len() - 1);
+    let (version,bytes) = GOLDEN.last().unwrap();
+    assert_eq!(*version, VERSION);
+    assert_eq!(save(&stores(), &watchers()), *bytes);
+  }
+
+  // After bumping VERSION, make its golden file with
+  // cargo test write_golden -- --ignored
+  #[test]
+  #[ignore]
+  fn write_golden() {
+    std::fs::write(format!("golden/snapshot-v{VERSION}.bin"), save(&stores(), &watchers())).unwrap();
+  }
+
+  #[test]
+  fn crc() {
+    assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+  }
+
+  #[test]
+  fn bad_snapshots() {
+    let err = |bytes: &[u8]| load(bytes).err().unwrap().to_string();
+    let bytes = save(&stores(), &watchers());
+
+    assert_eq!(err(b"nope"), "not an slkvs snapshot");
+    assert_eq!(err(b"slkvs\0\x09\x00"), "can't load snapshot version 9, newest is 7");
+    assert_eq!(err(b"slkvs\0\x00\x00"), "can't load snapshot version 0, newest is 7");
+    assert_eq!(err(&bytes[..bytes.len()-1]), "snapshot is truncated");
+
+    let mut extra = bytes.clone();
+    extra.push(0);
+    assert_eq!(err(&extra), "snapshot has 1 bytes left over");
+
+    let mut damaged = bytes.clone();
+    *damaged.last_mut().unwrap() ^= 1;
+    assert_eq!(err(&damaged), "snapshot is damaged");
+
+    // an empty worker
+    let (stores,_) = load(&save(&Stores::default(), &Watchers::default())).unwrap();
+    assert_eq!(stores.list(), Vec::<String>::new());
+  }
+}
@@ -0,0 +1,223 @@
+// Named stores, so that apps sharing a worker each get their own LeafPaths,
+// and dropping one doesn't wipe the others.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::acl::{Acl, Permission};
+use crate::hooks::{self, Post, Sender, HOOKS, QUEUE_LIMIT};
+use crate::schemas::SCHEMAS;
+use crate::tree::{DingString, LeafPaths, SchemaPath, Step};
+
+/// Top level keys that only admins may write under, since what's there
+/// decides what happens to the rest of the store.
+const ADMIN_ONLY: &[&str] = &[HOOKS, SCHEMAS];
+
+/// The paths in a store, and who may get at them.
+pub struct Store {
+  pub db: LeafPaths,
+  pub acl: Acl,
+  /// Why the last post to each hook failed, for the hooks where it did.
+  pub failed_hooks: BTreeMap<String,String>,
+  /// Posts waiting for deliverhooks, oldest first.
+  pub queued: VecDeque<Post>,
+  // new for every store made, so that a store dropped and made again with
+  // the same name is still a different store
+  id: u64,
+}
+
+impl Store {
+  pub fn new(db: LeafPaths, acl: Acl) -> Self {
+    Self { db, acl, failed_hooks: BTreeMap::new(), queued: VecDeque::new(), id: 0 }
+  }
+
+  /// Queue posts for deliverhooks. Past QUEUE_LIMIT the oldest are dropped,
+  /// as if posting them had failed.
+  pub fn queue(&mut self, posts: Vec<Post>) {
+    self.queued.extend(posts);
+    while self.queued.len() > QUEUE_LIMIT {
+      let Some(post) = self.queued.pop_front() else { break };
+      self.failed_hooks.insert(post.hook, format!("dropped without posting, with more than {QUEUE_LIMIT} posts waiting"));
+    }
+  }
+
+  /// Deliver what's queued, noting which hooks failed. How many posts are
+  /// still waiting.
+  pub fn deliver(&mut self, sender: &mut impl Sender) -> usize {
+    for (hook,rv) in hooks::deliver_queued(sender, &mut self.queued) {
+      match rv {
+        Ok(()) => self.failed_hooks.remove(&hook),
+        Err(err) => self.failed_hooks.insert(hook, err.to_string()),
+      };
+    }
+    self.queued.len()
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  /// Fails unless principal has needs at path. Writing at the root, or under
+  /// one of ADMIN_ONLY, needs admin.
+  pub fn check(&self, principal: &str, path: &SchemaPath, needs: Permission) -> Result<(), DingString> {
+    let admin_only = path.steps().is_empty()
+      || ADMIN_ONLY.iter().any(|top| path.steps().first() == Some(&Step::Key(top.to_string())));
+    let needs = match needs {
+      Permission::Write if admin_only => Permission::Admin,
+      needs => needs,
+    };
+    self.acl.check(principal, path, needs)
+  }
+
+  /// db, if principal has needs at path.
+  pub fn checked(&self, principal: &str, path: impl Into<SchemaPath>, needs: Permission) -> Result<&LeafPaths, DingString> {
+    self.check(principal, &path.into(), needs)?;
+    Ok(&self.db)
+  }
+
+  /// db for changing, if principal has needs at path.
+  pub fn checked_mut(&mut self, principal: &str, path: impl Into<SchemaPath>, needs: Permission) -> Result<&mut LeafPaths, DingString> {
+    self.check(principal, &path.into(), needs)?;
+    Ok(&mut self.db)
+  }
+}
+
+#[derive(Default)]
+pub struct Stores {
+  stores: BTreeMap<String,Store>,
+  // the last id given to a store
+  made: u64,
+}
+
+impl Stores {
+  /// owner gets admin on the whole of the new store.
+  pub fn create(&mut self, name: String, owner: String) -> Result<(), DingString> {
+    let mut acl = Acl::default();
+    acl.grant(owner, SchemaPath::from(vec![]), Permission::Admin);
+    self.insert(name, Store::new(LeafPaths::new(), acl))
+  }
+
+  /// Add a store that already has things in it, eg from a dump.
+  pub fn insert(&mut self, name: String, mut store: Store) -> Result<(), DingString> {
+    if self.stores.contains_key(&name) {
+      return Err(format!("store {name} already exists").into())
+    }
+    self.made += 1;
+    store.id = self.made;
+    self.stores.insert(name, store);
+    Ok(())
+  }
+
+  pub fn iter(&self) -> impl Iterator<Item=(&str,&Store)> {
+    self.stores.iter().map(|(name,store)| (name.as_str(), store))
+  }
+
+  pub fn list(&self) -> Vec<String> {
+    self.stores.keys().cloned().collect()
+  }
+
+  /// Remove the store and everything in it.
+  pub fn remove(&mut self, name: &str) -> Result<(), DingString> {
+    match self.stores.remove(name) {
+      Some(_) => Ok(()),
+      None => Err(Self::no_such_store(name)),
+    }
+  }
+
+  pub fn get(&self, name: &str) -> Result<&Store, DingString> {
+    self.stores.get(name).ok_or_else(|| Self::no_such_store(name))
+  }
+
+  pub fn get_mut(&mut self, name: &str) -> Result<&mut Store, DingString> {
+    self.stores.get_mut(name).ok_or_else(|| Self::no_such_store(name))
+  }
+
+  /// The store named name, as long as it's the one with id, rather than
+  /// another one made since with the same name.
+  pub fn get_made(&self, name: &str, id: u64) -> Result<&Store, DingString> {
+    self.get(name).and_then(|store| Self::made_as(name, id, store))
+  }
+
+  pub fn get_made_mut(&mut self, name: &str, id: u64) -> Result<&mut Store, DingString> {
+    self.get_mut(name).and_then(|store| Self::made_as(name, id, store))
+  }
+
+  fn made_as<S: std::borrow::Borrow<Store>>(name: &str, id: u64, store: S) -> Result<S, DingString> {
+    if store.borrow().id != id {
+      return Err(format!("store {name} has been dropped since").into())
+    }
+    Ok(store)
+  }
+
+  fn no_such_store(name: &str) -> DingString {
+    format!("no store named {name}").into()
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn separate_stores() {
+    let mut stores = Stores::default();
+    stores.create("uno".into(), "ann".into()).unwrap();
+    stores.create("due".into(), "ann".into()).unwrap();
+    assert_eq!(stores.list(), vec!["due", "uno"]);
+
+    stores.get_mut("uno").unwrap().db.add("wut".into(), "one".into());
+    stores.get_mut("due").unwrap().db.add("wut".into(), "two".into());
+    assert_eq!(stores.get("uno").unwrap().db.get("wut".into()), Some("one".into()));
+    assert_eq!(stores.get("due").unwrap().db.get("wut".into()), Some("two".into()));
+
+    stores.get_mut("uno").unwrap().db.clear();
+    assert_eq!(stores.get("uno").unwrap().db.get("wut".into()), None);
+    assert_eq!(stores.get("due").unwrap().db.get("wut".into()), Some("two".into()));
+
+    let err = stores.create("due".into(), "bob".into()).unwrap_err();
+    assert_eq!(err.to_string(), "store due already exists");
+
+    // the creator owns the store, and nobody else can get in
+    let uno = stores.get("uno").unwrap();
+    assert_eq!(uno.checked("ann", "wut", Permission::Admin).unwrap().get("wut".into()), None);
+    let err = uno.checked("bob", "wut", Permission::Read).err().unwrap();
+    assert_eq!(err.to_string(), "bob may not read wut");
+
+    // writing hooks needs admin, even for someone who can write everywhere else
+    let uno = stores.get_mut("uno").unwrap();
+    uno.acl.grant("bob".into(), "".into(), Permission::Write);
+    assert!(uno.checked("bob", "wut", Permission::Write).is_ok());
+    assert!(uno.checked("bob", "$hooks", Permission::Read).is_ok());
+    let err = uno.checked_mut("bob", "$hooks/spy/url", Permission::Write).err().unwrap();
+    assert_eq!(err.to_string(), "bob may not admin $hooks/spy/url");
+    assert!(uno.checked_mut("bob", "", Permission::Write).is_err());
+    assert!(uno.checked_mut("ann", "$hooks/spy/url", Permission::Write).is_ok());
+
+    // and so do schemas, including undoing them
+    assert!(uno.checked_mut("bob", "$schemas/any/schema", Permission::Write).is_err());
+    uno.db.undoable(|db| db.addschema("any".into(), "wut".into(), "true".into())).unwrap();
+    assert!(uno.db.undo_paths(1).any(|path| uno.check("bob", path, Permission::Write).is_err()));
+
+    // too many posts waiting
+    let post = |n: usize| Post { hook: format!("h{n}"), url: String::new(), body: String::new() };
+    uno.queue((0..QUEUE_LIMIT + 2).map(post).collect());
+    assert_eq!(uno.queued.len(), QUEUE_LIMIT);
+    assert_eq!(uno.queued.front(), Some(&post(2)));
+    assert_eq!(uno.failed_hooks.keys().collect::<Vec<_>>(), vec!["h0", "h1"]);
+    assert_eq!(uno.failed_hooks["h0"], format!("dropped without posting, with more than {QUEUE_LIMIT} posts waiting"));
+
+    stores.remove("due").unwrap();
+    assert_eq!(stores.list(), vec!["uno"]);
+    assert_eq!(stores.get("due").err().unwrap().to_string(), "no store named due");
+    assert_eq!(stores.remove("due").unwrap_err().to_string(), "no store named due");
+
+    // the same name again is a different store
+    let id = stores.get("uno").unwrap().id();
+    assert!(stores.get_made("uno", id).is_ok());
+    stores.remove("uno").unwrap();
+    stores.create("uno".into(), "bob".into()).unwrap();
+    assert_eq!(stores.get_made("uno", id).err().unwrap().to_string(), "store uno has been dropped since");
+    assert_eq!(stores.get_made_mut("due", id).err().unwrap().to_string(), "no store named due");
+  }
+}
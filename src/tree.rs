@@ -21,6 +21,18 @@ impl SchemaPath {
   pub fn singleton(step : Step) -> Self {
     Self(vec![step])
   }
+
+  pub fn steps(&self) -> &[Step] {
+    &self.0
+  }
+
+  /// Everything except the last step. The empty path is its own parent.
+  pub fn parent(&self) -> Self {
+    match self.0.split_last() {
+      Some((_,init)) => Self(init.to_vec()),
+      None => Self(vec![]),
+    }
+  }
 }
 
 impl std::fmt::Display for SchemaPath {
@@ -36,20 +48,26 @@ impl std::fmt::Display for SchemaPath {
 
 // convert a path like "root/things/3/name/first"
 // into &[Key("root"), Key("things"), Index(3), Key("name"), Key("first")]
+//
+// The empty string is the empty path, ie the root of the whole store.
 fn split_slash_path<S : AsRef<str>>(slash_sep : S) -> Vec<Step> {
+  let slash_sep = slash_sep.as_ref();
+  if slash_sep.is_empty() { return vec![] }
+
   slash_sep
-    .as_ref()
     .split('/')
-    .map(|path_step| {
-      // anything that is not parseable as an integer is treated as a key
-      match path_step.parse::<usize>() {
-        Ok(i) => Step::Index(i),
-        Err(_) => Step::Key(path_step.into()),
-      }
-    })
+    .map(parse_step)
     .collect::<Vec<_>>()
 }
 
+fn parse_step(path_step : &str) -> Step {
+  // anything that is not parseable as an integer is treated as a key
+  match path_step.parse::<usize>() {
+    Ok(i) => Step::Index(i),
+    Err(_) => Step::Key(path_step.into()),
+  }
+}
+
 // E0119 , so can't do mpl<S : AsRef<str>> From<S> for SchemaPath :-(
 impl From<&str> for SchemaPath {
   fn from(slash_sep: &str) -> Self {
@@ -69,7 +87,102 @@ impl From<Vec<Step>> for SchemaPath {
   }
 }
 
-use std::{collections::HashMap, ops::Add};
+/// A step in a PathPattern. Any matches exactly one key or index, AnyDepth
+/// matches any number of them, including none.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PatternStep {
+  Any,
+  AnyDepth,
+  Exactly(Step),
+}
+
+/// Like a SchemaPath, except that a "*" step matches any single step, and a
+/// "**" step matches any number of steps. So "web-app/servlet/*/servlet-name"
+/// matches the name of every servlet, and "web-app/**" matches everything
+/// under web-app.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathPattern(Vec<PatternStep>);
+
+impl PathPattern {
+  /// Does path match this pattern?
+  pub fn matches(&self, path : &[Step]) -> bool {
+    Self::matches_steps(&self.0, path)
+  }
+
+  fn matches_steps(pattern : &[PatternStep], path : &[Step]) -> bool {
+    match (pattern, path) {
+      ([], []) => true,
+      // try every possible number of steps for this to swallow
+      ([PatternStep::AnyDepth, pattern_rst @ ..], path) =>
+        (0..=path.len()).any(|skip| Self::matches_steps(pattern_rst, &path[skip..])),
+      ([PatternStep::Any, pattern_rst @ ..], [_, rst @ ..]) =>
+        Self::matches_steps(pattern_rst, rst),
+      ([PatternStep::Exactly(wanted), pattern_rst @ ..], [step, rst @ ..]) =>
+        wanted == step && Self::matches_steps(pattern_rst, rst),
+      _ => false,
+    }
+  }
+
+  /// The steps before the first wildcard. Every path matching the pattern is
+  /// under this prefix, so it narrows down the range that has to be scanned.
+  pub fn prefix(&self) -> SchemaPath {
+    let steps = self.0
+      .iter()
+      .map_while(|pattern_step| match pattern_step {
+        PatternStep::Any | PatternStep::AnyDepth => None,
+        PatternStep::Exactly(step) => Some(step.clone()),
+      })
+      .collect::<Vec<_>>();
+    SchemaPath(steps)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+}
+
+impl From<&str> for PathPattern {
+  fn from(slash_sep: &str) -> Self {
+    if slash_sep.is_empty() { return Self(vec![]) }
+
+    let steps = slash_sep
+      .split('/')
+      .map(|path_step| match path_step {
+        "*" => PatternStep::Any,
+        "**" => PatternStep::AnyDepth,
+        _ => PatternStep::Exactly(parse_step(path_step)),
+      })
+      .collect::<Vec<_>>();
+    Self(steps)
+  }
+}
+
+impl std::fmt::Display for PathPattern {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    let string_parts = self.0
+      .iter()
+      .map(|pattern_step| match pattern_step {
+        PatternStep::Any => "*".to_string(),
+        PatternStep::AnyDepth => "**".to_string(),
+        PatternStep::Exactly(step) => step.to_string(),
+      })
+      .collect::<Vec<_>>();
+
+    f.write_str(&string_parts.join("/"))
+  }
+}
+
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, ops::{Add, Bound, RangeBounds}};
+
+use rust_decimal::Decimal;
+
+use crate::diff::{self, Difference};
+use crate::feed::{Feed, FeedChange};
+use crate::history::History;
+use crate::index::{IndexKind, Indexes, NumberIndex, ValueIndex};
+use crate::schemas::{self, Schema, SCHEMAS};
+use crate::undo::UndoLog;
+use crate::view::{ReadView, Versioned};
 
 impl Add<Step> for SchemaPath
 {
@@ -178,6 +291,31 @@ impl From<&Leaf<String>> for serde_json::Value {
   }
 }
 
+impl Leaf<String> {
+  /// The value of a Number, None for anything else. Even strings that look
+  /// like numbers, because those are not numbers.
+  pub fn as_f64(&self) -> Option<f64> {
+    match self {
+      Leaf::Number(v) => v.parse().ok(),
+      _ => None,
+    }
+  }
+
+  /// Exact value of a Number, None for anything else. Err if the number is
+  /// too big or too precise for a Decimal, eg 1e300.
+  pub fn as_decimal(&self) -> Option<Result<Decimal, DingString>> {
+    match self {
+      Leaf::Number(v) => {
+        let decimal = Decimal::from_str_exact(v)
+          .or_else(|_| Decimal::from_scientific(v))
+          .map_err(|err| DingString(format!("{v} is not an exact decimal: {err}")));
+        Some(decimal)
+      }
+      _ => None,
+    }
+  }
+}
+
 impl<T, Src> From<Src> for Leaf<T>
 where
   T: LeafStorage + std::convert::From<Src>,
@@ -190,7 +328,30 @@ where
 
 // The storage for the keys and the values.
 type PathMap<K, V> = std::collections::BTreeMap<K, V>;
-pub struct LeafPaths(pub PathMap<SchemaPath,Leaf<String>>);
+pub struct LeafPaths {
+  // shared with any views, see view.rs
+  pub paths: Versioned<PathMap<SchemaPath,Leaf<String>>>,
+  // secondary indexes, by name
+  indexes: Indexes,
+  // goes up on every change, so anyone who remembers it can tell whether
+  // anything has changed since
+  revision: u64,
+  // the last few values of every path, with when they changed
+  history: History,
+  // milliseconds since the unix epoch, for history
+  clock: fn() -> u64,
+  // what to put back to take back the last few changes
+  undo: UndoLog,
+  // the last few changes in order, for polling
+  feed: Feed,
+}
+
+fn system_clock() -> u64 {
+  std::time::SystemTime::now()
+    .duration_since(std::time::UNIX_EPOCH)
+    .map(|since| since.as_millis() as u64)
+    .unwrap_or(0)
+}
 
 // 'Ding' cos that's what happens when you get an error.
 #[derive(Debug)]
@@ -202,13 +363,23 @@ impl std::fmt::Display for DingString {
   }
 }
 
+impl From<String> for DingString {
+  fn from(msg: String) -> Self {
+    Self(msg)
+  }
+}
+
 impl From<serde_json::Error> for DingString {
   fn from(err: serde_json::Error) -> Self {
     Self(format!("{err:?}"))
   }
 }
 
-use std::collections::BTreeMap;
+impl From<regex::Error> for DingString {
+  fn from(err: regex::Error) -> Self {
+    Self(err.to_string())
+  }
+}
 
 /**
   Need this to collect the results of a traverse_tree
@@ -222,7 +393,7 @@ use std::collections::BTreeMap;
   serde_json::Value might skip indexes, so the output is a sparse collection
   of indexes, and the easiest way to model that is a HashMap.
 */
-#[derive(Debug,PartialEq,Eq)]
+#[derive(Debug,Clone,PartialEq,Eq)]
 pub enum Collector {
   Empty,
 
@@ -236,6 +407,10 @@ pub enum Collector {
   Sparse(BTreeMap<usize,Collector>),
 
   Object(HashMap<String,Collector>),
+
+  // Placeholder for a subtree cut off by maxdepth in gettree. Carries the
+  // number of immediate children, so a UI can show them without fetching.
+  Truncated(usize),
 }
 
 impl From<&Leaf<String>> for Collector {
@@ -288,6 +463,48 @@ impl Collector {
         }
         Value::Object(values)
       }
+      // anything here could also be real data, so truncated says where these are
+      Self::Truncated(_) => Value::Null,
+    }
+  }
+
+  /// Where the tree was cut off by maxdepth, as paths from the root of the
+  /// tree, each with its child count.
+  pub fn truncated(&self) -> Vec<(SchemaPath,usize)> {
+    fn walk(collector : &Collector, steps : &mut Vec<Step>, found : &mut Vec<(SchemaPath,usize)>) {
+      match collector {
+        Collector::Truncated(n) => found.push((SchemaPath::from(steps.clone()), *n)),
+        Collector::Sparse(ary) => for (i,child) in ary {
+          steps.push(Step::Index(*i));
+          walk(child, steps, found);
+          steps.pop();
+        }
+        Collector::Object(map) => {
+          let mut keys = map.keys().collect::<Vec<_>>();
+          keys.sort();
+          for key in keys {
+            steps.push(Step::Key(key.clone()));
+            walk(&map[key], steps, found);
+            steps.pop();
+          }
+        }
+        _ => (),
+      }
+    }
+    let mut found = vec![];
+    walk(self, &mut vec![], &mut found);
+    found
+  }
+
+  /// The subtree steps below this, if there is one. gettree has the whole
+  /// way down from the root, so this is how to get at the part asked for.
+  pub fn below(&self, steps: &[Step]) -> Option<&Collector> {
+    match (steps.split_first(), self) {
+      (_, Collector::Empty) => None,
+      (None, subtree) => Some(subtree),
+      (Some((Step::Key(k),rst)), Collector::Object(children)) => children.get(k)?.below(rst),
+      (Some((Step::Index(i),rst)), Collector::Sparse(children)) => children.get(i)?.below(rst),
+      _ => None,
     }
   }
 }
@@ -326,32 +543,468 @@ impl Into<serde_json::Value> for Collector {
   fn into(self) -> serde_json::Value { (&self).into() }
 }
 
+/// The value of a FlatNode. Containers are empty here, because their
+/// contents are the other nodes which have them as parent.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlatValue {
+  Null,
+  Bool(bool),
+  Number(String),
+  String(String),
+  Array,
+  Object,
+  Truncated(usize),
+}
+
+/// One node of a Collector flattened into a list, for crossing the wit
+/// boundary, which has no recursive types. The root is the first node and has
+/// no parent. Every other node has the index of its parent, which is always
+/// earlier in the list, and the step from that parent to itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlatNode {
+  pub parent: Option<usize>,
+  pub step: Option<Step>,
+  pub value: FlatValue,
+}
+
+impl Collector {
+  /// Depth-first, with object keys in order, so the same tree always gives the same list.
+  pub fn flatten(&self) -> Vec<FlatNode> {
+    let mut nodes = vec![];
+    Self::flatten_into(self, None, None, &mut nodes);
+    nodes
+  }
+
+  fn flatten_into(collector : &Collector, parent : Option<usize>, step : Option<Step>, nodes : &mut Vec<FlatNode>) {
+    let index = nodes.len();
+    let value = match collector {
+      // an Empty in a tree is a container that never got anything put in it
+      Collector::Empty | Collector::Null => FlatValue::Null,
+      Collector::Bool(v) => FlatValue::Bool(*v),
+      Collector::Number(v) => FlatValue::Number(v.clone()),
+      Collector::String(v) => FlatValue::String(v.clone()),
+      Collector::Sparse(_) => FlatValue::Array,
+      Collector::Object(_) => FlatValue::Object,
+      Collector::Truncated(n) => FlatValue::Truncated(*n),
+    };
+    nodes.push(FlatNode { parent, step, value });
+
+    match collector {
+      Collector::Sparse(ary) => {
+        for (i,child) in ary {
+          Self::flatten_into(child, Some(index), Some(Step::Index(*i)), nodes);
+        }
+      }
+      Collector::Object(map) => {
+        let mut keys = map.keys().collect::<Vec<_>>();
+        keys.sort();
+        for key in keys {
+          Self::flatten_into(&map[key], Some(index), Some(Step::Key(key.clone())), nodes);
+        }
+      }
+      _ => (),
+    }
+  }
+
+  /// The reverse of flatten. An empty list gives Collector::Empty.
+  pub fn unflatten(nodes : &[FlatNode]) -> Result<Collector, DingString> {
+    let ding = |i : usize, msg : &str| Err(DingString(format!("node {i}: {msg}")));
+
+    // make every node, with empty containers
+    let mut collectors = Vec::with_capacity(nodes.len());
+    for (i,node) in nodes.iter().enumerate() {
+      match (i, node.parent) {
+        (0, None) => (),
+        (0, Some(_)) => return ding(i, "the first node is the root, so it can't have a parent"),
+        (_, None) => return ding(i, "only the first node can be without a parent"),
+        (_, Some(parent)) if parent >= i => return ding(i, "parent must come before its children"),
+        (_, Some(_)) => (),
+      }
+
+      let collector = match &node.value {
+        FlatValue::Null => Collector::Null,
+        FlatValue::Bool(v) => Collector::Bool(*v),
+        FlatValue::Number(v) => {
+          if v.parse::<serde_json::Number>().is_err() { return ding(i, &format!("{v} is not a number")) }
+          Collector::Number(v.clone())
+        }
+        FlatValue::String(v) => Collector::String(v.clone()),
+        FlatValue::Array => Collector::Sparse(BTreeMap::new()),
+        FlatValue::Object => Collector::Object(HashMap::new()),
+        FlatValue::Truncated(n) => Collector::Truncated(*n),
+      };
+      collectors.push(Some(collector));
+    }
+
+    // Children always come after their parents, so going backwards means a
+    // node has all its own children by the time it is moved into its parent.
+    for (i,node) in nodes.iter().enumerate().skip(1).rev() {
+      // parent was checked above
+      let parent = node.parent.unwrap();
+      let child = collectors[i].take().unwrap();
+      match (&node.step, collectors[parent].as_mut().unwrap()) {
+        (Some(Step::Index(index)), Collector::Sparse(ary)) => { ary.insert(*index, child); },
+        (Some(Step::Key(key)), Collector::Object(map)) => { map.insert(key.clone(), child); },
+        (_, Collector::Sparse(_)) => return ding(i, "child of an array needs an index"),
+        (_, Collector::Object(_)) => return ding(i, "child of an object needs a key"),
+        _ => return ding(i, "parent is not an array or an object"),
+      }
+    }
+
+    Ok(collectors.first_mut().and_then(Option::take).unwrap_or(Collector::Empty))
+  }
+}
+
+/// How find compares the text of a leaf value.
+#[derive(Debug, Clone)]
+pub enum Matcher {
+  Exact(String),
+  Prefix(String),
+  Substring(String),
+  Regex(regex::Regex),
+}
+
+impl Matcher {
+  pub fn regex(pattern: &str) -> Result<Self, DingString> {
+    Ok(Self::Regex(regex::Regex::new(pattern)?))
+  }
+
+  pub fn matches(&self, text: &str) -> bool {
+    match self {
+      Self::Exact(v) => text == v,
+      Self::Prefix(v) => text.starts_with(v.as_str()),
+      Self::Substring(v) => text.contains(v.as_str()),
+      Self::Regex(re) => re.is_match(text),
+    }
+  }
+}
+
+/// A position for paging through everything under a prefix. All it keeps is
+/// the last path it returned, and it seeks past that for the next page, so it
+/// still carries on from the right place if the store changes between pages.
+pub struct ScanCursor {
+  prefix: SchemaPath,
+  last: Option<SchemaPath>,
+}
+
+impl ScanCursor {
+  pub fn new(prefix: String) -> Self {
+    Self { prefix: prefix.into(), last: None }
+  }
+
+  /// The next n paths with their values, or fewer at the end. Empty once
+  /// there are no more.
+  pub fn next(&mut self, leaf_paths: &LeafPaths, n: usize) -> Vec<(String,Leaf<String>)> {
+    let bound = match &self.last {
+      Some(last) => Bound::Excluded(last),
+      None => Bound::Included(&self.prefix),
+    };
+    let mut cursor = leaf_paths.paths.lower_bound(bound);
+
+    // n comes straight from the caller, so don't allocate for it up front
+    let mut page = vec![];
+    while page.len() < n {
+      match cursor.next() {
+        Some((k,v)) if k.0.starts_with(&self.prefix.0) => page.push((k,v)),
+        _ => break,
+      }
+    }
+
+    if let Some((k,_)) = page.last() {
+      self.last = Some((*k).clone());
+    }
+    page.into_iter().map(|(k,v)| (k.to_string(), v.clone())).collect()
+  }
+}
+
+/// What aggregate works out from the numbers it finds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Aggregation {
+  Sum,
+  Min,
+  Max,
+  Avg,
+  Count,
+}
+
 // This provides a thin wrapper around the BTree/Hash map and implements
 // function calls coming in from the component. Because it's easier to write
 // tests this way.
 impl LeafPaths {
   pub fn new() -> Self {
-    Self(PathMap::new())
+    Self {
+      paths: Versioned::new(PathMap::new()),
+      indexes: Indexes::default(),
+      revision: 0,
+      history: History::default(),
+      clock: system_clock,
+      undo: UndoLog::default(),
+      feed: Feed::default(),
+    }
+  }
+
+  /// Where history gets its times from. Tests need times that don't change.
+  #[cfg(test)]
+  pub fn set_clock(&mut self, clock: fn() -> u64) {
+    self.clock = clock;
+  }
+
+  pub fn revision(&self) -> u64 {
+    self.revision
+  }
+
+  /// Carry on from revision, eg after restoring a dump. So that revisions
+  /// from before the dump still compare sensibly with revisions after. The
+  /// feed starts again from revision.
+  pub fn set_revision(&mut self, revision: u64) {
+    self.revision = revision;
+    self.feed.restart(revision);
+  }
+
+  /// The paths as they are now, unaffected by any later changes. Only for
+  /// reading, so it doesn't need indexes or history.
+  pub fn view(&self) -> ReadView {
+    ReadView::new(Self {
+      paths: self.paths.share(),
+      indexes: Indexes::default(),
+      revision: self.revision,
+      history: History::new(0, 0),
+      clock: self.clock,
+      undo: UndoLog::new(0),
+      feed: Feed::new(0),
+    })
+  }
+
+  /// The schemas whose prefix is at, above or below one of paths, which are
+  /// the only ones a change at paths can make a difference to.
+  fn schemas_over(&self, paths: &[SchemaPath]) -> Vec<Schema> {
+    let mut schemas = schemas::schemas(self);
+    schemas.retain(|schema| {
+      let prefix = schema.prefix.steps();
+      paths.iter().any(|path| prefix.starts_with(path.steps()) || path.steps().starts_with(prefix))
+    });
+    schemas
+  }
+
+  /// Just the subtrees at paths, for trying changes on. Only one step to
+  /// undo is kept, and no history or feed.
+  fn scratch<'a>(&self, paths: impl Iterator<Item=&'a SchemaPath>) -> Self {
+    let mut scratch = Self {
+      paths: Versioned::new(PathMap::new()),
+      indexes: Indexes::default(),
+      revision: self.revision,
+      history: History::new(0, 0),
+      clock: self.clock,
+      undo: UndoLog::new(1),
+      feed: Feed::new(0),
+    };
+    for path in paths {
+      for (path,leaf) in self.subtree_range(path.clone()) {
+        scratch.paths.insert(path.clone(), leaf.clone());
+      }
+    }
+    scratch
+  }
+
+  /// Fails unless each of schemas whose subtree is different in scratch
+  /// matches it there.
+  fn check_changed(&self, scratch: &Self, schemas: &[Schema]) -> Result<(), DingString> {
+    for schema in schemas {
+      let prefix = schema.prefix.to_string();
+      if !scratch.diff_with(prefix.clone(), self, prefix).is_empty() {
+        schema.check(scratch)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Fails if changes would leave the subtree at the prefix of any schema not
+  /// matching it. Nothing here changes either way.
+  pub fn check_schemas(&self, changes: &[(SchemaPath,Option<Leaf<String>>)]) -> Result<(), DingString> {
+    let paths = changes.iter().map(|(path,_)| path.clone()).collect::<Vec<_>>();
+    let schemas = self.schemas_over(&paths);
+    if schemas.is_empty() { return Ok(()) }
+
+    let mut scratch = self.scratch(schemas.iter().map(|schema| &schema.prefix));
+    scratch.apply(changes.to_vec());
+    self.check_changed(&scratch, &schemas)
+  }
+
+  /// Run f, which only changes things at or below path, unless it would
+  /// leave the subtree at the prefix of any schema not matching it.
+  pub fn validated<T>(&mut self, path: &str, f: impl FnOnce(&mut Self) -> Result<T, DingString>) -> Result<T, DingString> {
+    let path = SchemaPath::from(path);
+    let schemas = self.schemas_over(std::slice::from_ref(&path));
+    if schemas.is_empty() { return f(self) }
+
+    // f runs once, on a copy of what it can change and what the schemas
+    // look at, and what it changed there is copied back if they match.
+    let mut scratch = self.scratch(std::iter::once(&path).chain(schemas.iter().map(|schema| &schema.prefix)));
+    let rv = scratch.undoable(f)?;
+    self.check_changed(&scratch, &schemas)?;
+    let changed = scratch.undo.take(1).into_iter().flat_map(|step| step.into_keys());
+    let changes = changed.map(|path| { let leaf = scratch.paths.get(&path).cloned(); (path, leaf) }).collect::<Vec<_>>();
+    self.apply(changes);
+    Ok(rv)
+  }
+
+  /// Keep the subtree at prefix matching schema from now on, replacing any
+  /// schema with the same name. Fails if schema isn't a json schema, or if
+  /// what's there already doesn't match it.
+  pub fn addschema(&mut self, name: String, prefix: String, schema: String) -> Result<(), DingString> {
+    schemas::compile(&schema)?;
+    let attached = Schema { name: name.clone(), prefix: prefix.as_str().into(), schema: schema.clone() };
+    attached.check(self)?;
+    self.insert(vec![Step::Key(SCHEMAS.into()), Step::Key(name.clone()), Step::Key("prefix".into())].into(), Leaf::String(prefix));
+    self.insert(vec![Step::Key(SCHEMAS.into()), Step::Key(name), Step::Key("schema".into())].into(), Leaf::String(schema));
+    Ok(())
+  }
+
+  pub fn dropschema(&mut self, name: String) {
+    let path: SchemaPath = vec![Step::Key(SCHEMAS.into()), Step::Key(name)].into();
+    let paths = self.subtree_range(path).map(|(path,_)| path.clone()).collect::<Vec<_>>();
+    for path in paths {
+      self.remove(&path);
+    }
+  }
+
+  /// Run f as one step, which undo takes back all in one go.
+  pub fn undoable<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+    let started = self.undo.begin();
+    let rv = f(self);
+    // only the outermost finishes
+    if started { self.undo.finish() }
+    rv
+  }
+
+  /// Take back the last n undoable steps, newest first, putting back
+  /// whatever they overwrote or deleted. Returns how many were taken back,
+  /// which is fewer than n if there aren't that many.
+  pub fn undo(&mut self, n: usize) -> usize {
+    let steps = self.undo.take(n);
+    let undone = steps.len();
+    for step in steps {
+      self.apply(step);
+    }
+    undone
+  }
+
+  /// Paths that undo(n) would change.
+  pub fn undo_paths(&self, n: usize) -> impl Iterator<Item=&SchemaPath> {
+    self.undo.paths(n)
+  }
+
+  /// What undo(n) would put back, in the order it would.
+  pub fn undo_changes(&self, n: usize) -> Vec<(SchemaPath,Option<Leaf<String>>)> {
+    self.undo.changes(n).map(|(path,leaf)| (path.clone(), leaf.clone())).collect()
+  }
+
+  pub fn feed_log(&self) -> &Feed {
+    &self.feed
+  }
+
+  pub fn feed_log_mut(&mut self) -> &mut Feed {
+    &mut self.feed
+  }
+
+  /// Up to limit changes at or below prefix after revision since, oldest
+  /// first, and the revision to ask for next time.
+  pub fn changes(&self, since: u64, prefix: String, limit: usize) -> Result<(Vec<FeedChange>,u64), DingString> {
+    let (changes,next) = self.feed.after(since, self.revision, &prefix.into(), limit)?;
+    Ok((changes.into_iter().cloned().collect(), next))
+  }
+
+  pub fn history_log(&self) -> &History {
+    &self.history
+  }
+
+  pub fn history_log_mut(&mut self) -> &mut History {
+    &mut self.history
+  }
+
+  /// The last limit changes to path, newest first, each with when it
+  /// happened. None for a delete.
+  pub fn history(&self, path: String, limit: usize) -> Vec<(u64,Option<Leaf<String>>)> {
+    self.history
+      .recent(&path.into(), limit)
+      .into_iter()
+      .map(|change| (change.at, change.leaf.clone()))
+      .collect()
+  }
+
+  /// What path had in it at time at, in milliseconds since the unix epoch.
+  /// Fails if that's from before the history that's kept.
+  pub fn getat(&self, path: String, at: u64) -> Result<Option<Leaf<String>>, DingString> {
+    Ok(self.history.at(&path.into(), at)?.cloned())
+  }
+
+  pub fn index_definitions(&self) -> impl Iterator<Item=(&str,IndexKind,&PathPattern)> {
+    self.indexes.definitions()
   }
 
   pub fn get(&self, path: String) -> Option<String> {
     let path: SchemaPath = path.into();
-    match self.0.get(&path) {
+    match self.paths.get(&path) {
       None => None,
       Some(v) => Some(format!("{v}")),
     }
   }
 
+  /// Same as get, but keeping the type of the value.
+  pub fn getvalue(&self, path: String) -> Option<Leaf<String>> {
+    let path: SchemaPath = path.into();
+    self.paths.get(&path).cloned()
+  }
+
+  /// Same as add, but keeping the type of the value. Fails for a Number
+  /// that isn't a json number, because gettree can't do anything with it.
+  pub fn setvalue(&mut self, path: String, leaf: Leaf<String>) -> Result<(), DingString> {
+    if let Leaf::Number(v) = &leaf {
+      if v.parse::<serde_json::Number>().is_err() {
+        return Err(DingString(format!("{v} is not a number")))
+      }
+    }
+    self.insert(path.into(), leaf);
+    Ok(())
+  }
+
   pub fn listpaths(&self) -> Vec<String> {
-    self.0.keys().map(ToString::to_string).collect()
+    self.paths.keys().map(ToString::to_string).collect()
+  }
+
+  /// All paths under prefix, each with its value. Saves a get per path.
+  pub fn listentries(&self, prefix: String) -> Vec<(String,Leaf<String>)> {
+    self.subtree_paths(prefix.into())
+      .into_iter()
+      .map(|(path,leaf)| (path.to_string(), leaf))
+      .collect()
+  }
+
+  /// (name, prefix, value) for each <top>/<name> with both a prefix and a
+  /// field, eg $hooks/<name>/prefix and $hooks/<name>/url. Ones missing
+  /// either, or where they aren't strings, are left out.
+  pub fn records(&self, top: &str, field: &str) -> Vec<(String,SchemaPath,String)> {
+    let mut found: BTreeMap<String,(Option<String>,Option<String>)> = BTreeMap::new();
+    for (path,leaf) in self.subtree_paths(vec![Step::Key(top.into())].into()) {
+      let Leaf::String(v) = leaf else { continue };
+      let [_, name, key] = path.steps() else { continue };
+      let record = found.entry(name.to_string()).or_default();
+      match key.to_string() {
+        key if key == "prefix" => record.0 = Some(v),
+        key if key == field => record.1 = Some(v),
+        _ => (),
+      }
+    }
+    found
+      .into_iter()
+      .filter_map(|(name,(prefix,v))| Some((name, prefix?.into(), v?)))
+      .collect()
   }
 
   /// Given a path, provide all subpaths with their values.
   fn subtree_paths(&self, path: SchemaPath) -> Vec<(SchemaPath,Leaf<String>)> {
-    use std::ops::Bound;
-
     // find the first matching path
-    let mut cursor = self.0.lower_bound(Bound::Included(&path));
+    let mut cursor = self.paths.lower_bound(Bound::Included(&path));
     // cache to obviate repeated calculation
     let path_len = path.0.len();
     // result goes here
@@ -374,8 +1027,223 @@ impl LeafPaths {
     filtered_paths
   }
 
+  /// Iterate over path and everything below it, in path order, without
+  /// collecting anything.
+  fn subtree_range(&self, path: SchemaPath) -> impl Iterator<Item=(&SchemaPath,&Leaf<String>)> {
+    self.paths
+      .range((Bound::Included(path.clone()), Bound::Unbounded))
+      .take_while(move |(k,_)| k.0.starts_with(&path.0))
+  }
+
+  /// A path at or below prefix with both a value and things under it, which
+  /// gettree can't make into json.
+  pub fn mixed(&self, prefix: SchemaPath) -> Option<SchemaPath> {
+    let mut paths = self.subtree_range(prefix).map(|(path,_)| path).peekable();
+    while let Some(path) = paths.next() {
+      // anything under path comes straight after it
+      if paths.peek().is_some_and(|next| next.0.starts_with(&path.0)) {
+        return Some(path.clone())
+      }
+    }
+    None
+  }
+
+  /// The leaves that differ between the subtrees at a and b, with paths
+  /// relative to them.
+  pub fn diff(&self, a: String, b: String) -> Vec<Difference> {
+    self.diff_with(a, self, b)
+  }
+
+  /// Same as diff, except b is in other, eg a later version of this.
+  pub fn diff_with(&self, a: String, other: &LeafPaths, b: String) -> Vec<Difference> {
+    let (a,b) = (SchemaPath::from(a), SchemaPath::from(b));
+    let (a_len,b_len) = (a.0.len(), b.0.len());
+    diff::merge(
+      self.subtree_range(a).map(|(path,leaf)| (SchemaPath(path.0[a_len..].to_vec()), leaf)),
+      other.subtree_range(b).map(|(path,leaf)| (SchemaPath(path.0[b_len..].to_vec()), leaf)),
+    )
+  }
+
+  /// Is there a leaf at path, or anything below it?
+  pub fn exists(&self, path: String) -> bool {
+    let path: SchemaPath = path.into();
+    // The first key at or after path is either path itself, or the first
+    // path below it. If it's neither, there is nothing here.
+    let cursor = self.paths.lower_bound(Bound::Included(&path));
+    match cursor.peek_next() {
+      Some((k,_)) => k.0.starts_with(&path.0),
+      None => false,
+    }
+  }
+
+  /// Paths at or below prefix whose value matches. Values are compared as
+  /// the same text that get returns, so numbers and booleans can match too.
+  pub fn find(&self, matcher: &Matcher, prefix: Option<String>) -> Vec<String> {
+    let prefix: SchemaPath = prefix.unwrap_or_default().into();
+    self.subtree_range(prefix)
+      .filter(|(_,leaf)| matcher.matches(&leaf.to_string()))
+      .map(|(path,_)| path.to_string())
+      .collect()
+  }
+
+  /// Number of leaves at or below prefix.
+  pub fn count(&self, prefix: String) -> usize {
+    self.subtree_range(prefix.into()).count()
+  }
+
+  /// Iterate over the paths that match pattern, in path order.
+  fn pattern_range<'a>(&'a self, pattern: &'a PathPattern) -> impl Iterator<Item=(&'a SchemaPath,&'a Leaf<String>)> {
+    self.subtree_range(pattern.prefix())
+      .filter(|(k,_)| pattern.matches(&k.0))
+  }
+
+  // All writes go through here, so the indexes, revision, history, undo and
+  // feed stay up to date.
   fn insert(&mut self, path : SchemaPath, leaf : Leaf<String>) -> Option<Leaf<String>> {
-    self.0.insert(path, leaf)
+    self.indexes.update(&path, self.paths.get(&path), Some(&leaf));
+    self.history.record(&path, (self.clock)(), Some(&leaf));
+    self.undo.record(&path, self.paths.get(&path));
+    self.revision += 1;
+    self.feed.record(self.revision, &path, Some(&leaf));
+    self.paths.insert(path, leaf)
+  }
+
+  // All deletes go through here, so the indexes, revision, history, undo and
+  // feed stay up to date.
+  fn remove(&mut self, path : &SchemaPath) -> Option<Leaf<String>> {
+    self.indexes.update(path, self.paths.get(path), None);
+    let prev = self.paths.remove(path);
+    if prev.is_some() {
+      self.revision += 1;
+      self.history.record(path, (self.clock)(), None);
+      self.undo.record(path, prev.as_ref());
+      self.feed.record(self.revision, path, None);
+    }
+    prev
+  }
+
+  /// Remove all paths and values. Indexes are kept, but emptied.
+  pub fn clear(&mut self) {
+    let now = (self.clock)();
+    for (path,leaf) in self.paths.iter() {
+      self.history.record(path, now, None);
+      self.undo.record(path, Some(leaf));
+    }
+    // rather than copying for any views, only to clear the copy
+    self.paths = Versioned::new(PathMap::new());
+    self.indexes.clear();
+    self.revision += 1;
+    // one change for the lot
+    self.feed.record(self.revision, &SchemaPath(vec![]), None);
+  }
+
+  /// Make a batch of changes in one go. None means delete.
+  pub fn apply(&mut self, changes: impl IntoIterator<Item=(SchemaPath,Option<Leaf<String>>)>) {
+    for (path,leaf) in changes {
+      match leaf {
+        Some(leaf) => { self.insert(path, leaf); }
+        None => { self.remove(&path); }
+      }
+    }
+  }
+
+  fn index_pattern(pattern: String) -> Result<PathPattern, DingString> {
+    let pattern = PathPattern::from(pattern.as_str());
+    if pattern.is_empty() {
+      return Err(DingString("index pattern must have at least one step".into()))
+    }
+    Ok(pattern)
+  }
+
+  /// Index the values at paths matching pattern, replacing any existing
+  /// index with the same name.
+  pub fn addindex(&mut self, name: String, pattern: String) -> Result<(), DingString> {
+    let pattern = Self::index_pattern(pattern)?;
+    let index = ValueIndex::new(pattern.clone(), self.pattern_range(&pattern));
+    self.indexes.insert_value_index(name, index);
+    Ok(())
+  }
+
+  /// Keep the numbers at paths matching pattern in order, replacing any
+  /// existing index with the same name. numrange uses this for queries with
+  /// the same pattern.
+  pub fn addnumindex(&mut self, name: String, pattern: String) -> Result<(), DingString> {
+    let pattern = Self::index_pattern(pattern)?;
+    let index = NumberIndex::new(pattern.clone(), self.pattern_range(&pattern));
+    self.indexes.insert_number_index(name, index);
+    Ok(())
+  }
+
+  pub fn dropindex(&mut self, name: String) {
+    self.indexes.remove(&name);
+  }
+
+  /// Parent paths of the leaves in the named index whose value is value.
+  pub fn lookup(&self, name: String, value: String) -> Result<Vec<String>, DingString> {
+    match self.indexes.value_index(&name) {
+      Some(index) => Ok(index.lookup(&value).map(ToString::to_string).collect()),
+      None => Err(DingString(format!("no index named {name}"))),
+    }
+  }
+
+  /// Work out op over the numbers at paths matching pattern, using exact
+  /// decimal arithmetic. Anything that isn't a Number is skipped.
+  ///
+  /// None when there are no numbers, except that the Sum and Count of no
+  /// numbers is 0.
+  pub fn aggregate(&self, pattern: String, op: Aggregation) -> Result<Option<Decimal>, DingString> {
+    let pattern = PathPattern::from(pattern.as_str());
+    let overflow = || DingString(format!("overflow calculating {op:?} of {pattern}"));
+
+    let mut count = 0usize;
+    let mut acc : Option<Decimal> = None;
+    for (_,leaf) in self.pattern_range(&pattern) {
+      let Some(number) = leaf.as_decimal() else { continue };
+      let number = number?;
+      count += 1;
+      acc = Some(match (op, acc) {
+        (_, None) => number,
+        (Aggregation::Sum | Aggregation::Avg, Some(acc)) => acc.checked_add(number).ok_or_else(overflow)?,
+        (Aggregation::Min, Some(acc)) => acc.min(number),
+        (Aggregation::Max, Some(acc)) => acc.max(number),
+        // count doesn't need the numbers themselves
+        (Aggregation::Count, Some(acc)) => acc,
+      });
+    }
+
+    match (op, acc) {
+      (Aggregation::Count, _) => Ok(Some(Decimal::from(count))),
+      (Aggregation::Sum, None) => Ok(Some(Decimal::ZERO)),
+      (Aggregation::Avg, Some(sum)) => {
+        let avg = sum.checked_div(Decimal::from(count)).ok_or_else(overflow)?;
+        Ok(Some(avg.normalize()))
+      }
+      (_, acc) => Ok(acc),
+    }
+  }
+
+  /// Paths of the numbers between lower and upper, compared as numbers, at
+  /// paths matching pattern. No pattern means anywhere. Fails for a NaN
+  /// bound, which nothing is above or below.
+  pub fn numrange(&self, lower: Bound<f64>, upper: Bound<f64>, pattern: Option<String>) -> Result<Vec<String>, DingString> {
+    for bound in [lower, upper] {
+      if let Bound::Included(v) | Bound::Excluded(v) = bound {
+        if v.is_nan() { return Err(DingString("a range can't have NaN as a bound".into())) }
+      }
+    }
+    let pattern = PathPattern::from(pattern.as_deref().unwrap_or("**"));
+
+    if let Some(index) = self.indexes.number_index_for(&pattern) {
+      // index is in number order, but results are in path order like everywhere else
+      let mut paths = index.range(lower, upper).collect::<Vec<_>>();
+      paths.sort();
+      return Ok(paths.into_iter().map(ToString::to_string).collect())
+    }
+
+    Ok(self.pattern_range(&pattern)
+      .filter(|(_,leaf)| leaf.as_f64().is_some_and(|v| (lower,upper).contains(&v)))
+      .map(|(path,_)| path.to_string())
+      .collect())
   }
 
   pub fn add(&mut self, path: String, leaf: String) {
@@ -406,11 +1274,56 @@ impl LeafPaths {
   }
 
   pub fn addtree(&mut self, path: String, json: String) -> Result<(), DingString> {
-    let json = serde_json::from_str(json.as_str())?;
+    let json: serde_json::Value = serde_json::from_str(json.as_str())?;
+    if path.is_empty() && !(json.is_object() || json.is_array()) {
+      return Err(DingString("the root can't have a value, only things under it".into()))
+    }
     self.add_at_path(path.into(), json);
     Ok(())
   }
 
+  // Same as add_at_path, but from a Collector, so sparse arrays keep their indexes.
+  fn add_collector_at_path(&mut self, base_path : SchemaPath, collector: &Collector) -> Result<(), DingString> {
+    match collector {
+      Collector::Empty => (),
+      Collector::Null => { self.insert(base_path, Leaf::Null); },
+      Collector::Bool(v) => { self.insert(base_path, Leaf::Boolean(*v)); },
+      Collector::Number(v) => { self.insert(base_path, Leaf::Number(v.clone())); },
+      Collector::String(v) => { self.insert(base_path, Leaf::String(v.clone())); },
+      Collector::Sparse(ary) => {
+        for (i, child) in ary {
+          self.add_collector_at_path(&base_path + Step::Index(*i), child)?;
+        }
+      }
+      Collector::Object(map) => {
+        for (key, child) in map {
+          self.add_collector_at_path(&base_path + Step::Key(key.clone()), child)?;
+        }
+      }
+      Collector::Truncated(_) => return Err(DingString(format!("can't put a truncated subtree at {base_path}"))),
+    };
+    Ok(())
+  }
+
+  /// Like addtree, but with the tree as a Collector rather than json. A
+  /// truncated subtree anywhere means nothing at all is added.
+  pub fn puttree(&mut self, path: String, tree: Collector) -> Result<(), DingString> {
+    fn is_truncated(collector : &Collector) -> bool {
+      match collector {
+        Collector::Truncated(_) => true,
+        Collector::Sparse(ary) => ary.values().any(is_truncated),
+        Collector::Object(map) => map.values().any(is_truncated),
+        _ => false,
+      }
+    }
+
+    if is_truncated(&tree) { return Err(DingString(format!("can't put a truncated subtree at {path}"))) }
+    if path.is_empty() && !matches!(tree, Collector::Object(_) | Collector::Sparse(_) | Collector::Empty) {
+      return Err(DingString("the root can't have a value, only things under it".into()))
+    }
+    self.add_collector_at_path(path.into(), &tree)
+  }
+
   #[allow(dead_code,unused_variables)]
   fn append_value(parent : &serde_json::Value, step : &Step, value : &Leaf<String>) -> serde_json::Value {
     serde_json::Value::Null
@@ -422,9 +1335,17 @@ impl LeafPaths {
   // either a new collection (ie array or map), or an individual value.
   //
   // rcp is "recipient", which is kinda like an io, except tree-structured.
-  fn traverse_tree<'a,'b>(path: &'a [Step], value: &'a Leaf<String>, rcp : &'b mut Collector) {
+  //
+  // value is usually a &Leaf<String>, but can also be a ready-made Collector,
+  // eg the Truncated placeholder.
+  fn traverse_tree<'a,'b,V : Into<Collector>>(path: &'a [Step], value: V, rcp : &'b mut Collector) {
     // Essentially, a path step is either a key or an index; and a value is a collection or a naked value.
     match (path, rcp) {
+      // no steps at all, so value replaces whatever is here
+      ([], rcp) => {
+        *rcp = value.into();
+      },
+
       // last step, therefore we can insert value
       ([Step::Key(k)], Collector::Object(ref mut map)) => {
         map.insert(k.into(),value.into());
@@ -439,11 +1360,11 @@ impl LeafPaths {
       ([Step::Key(k), rst @ .. ], Collector::Object(ref mut map)) => {
         if let Some(intermediate) = map.get_mut(k) {
           // we already have an object at this key, so reuse it
-          Self::traverse_tree(rst, &value, intermediate);
+          Self::traverse_tree(rst, value, intermediate);
         } else {
           // Dunno yet what kind of object it's going to be
           let mut intermediate = Collector::Empty;
-          Self::traverse_tree(rst, &value, &mut intermediate);
+          Self::traverse_tree(rst, value, &mut intermediate);
           map.insert(k.into(),intermediate);
         }
       }
@@ -451,11 +1372,11 @@ impl LeafPaths {
       ([Step::Index(i), rst @ ..], Collector::Sparse(ref mut ary)) => {
         if let Some(intermediate) = ary.get_mut(i) {
           // we already have an object at this index, so reuse it
-          Self::traverse_tree(rst, &value, intermediate);
+          Self::traverse_tree(rst, value, intermediate);
         } else {
           // Dunno yet what kind of object it's going to be
           let mut intermediate = Collector::Empty;
-          Self::traverse_tree(rst, &value, &mut intermediate);
+          Self::traverse_tree(rst, value, &mut intermediate);
           ary.insert(*i, intermediate);
         }
       }
@@ -478,22 +1399,48 @@ impl LeafPaths {
   }
 
   /// Fetch an entire subtree, as a string representation of the json rooted at that path.
-  pub fn gettree(&self, path: String) -> Collector {
+  ///
+  /// With a maxdepth, anything more than maxdepth steps below path is replaced
+  /// by a Collector::Truncated carrying the number of children at the cutoff.
+  pub fn gettree(&self, path: String, maxdepth: Option<usize>) -> Collector {
     // fetch all subtree paths with their values
     let path = SchemaPath::from(path);
+    let cutoff = maxdepth.map(|depth| path.0.len() + depth);
     let subtree_path_values = self.subtree_paths(path);
 
     // ok build the object
     let mut obj = Collector::Empty;
-    for (schema_path,value) in subtree_path_values {
-      LeafPaths::traverse_tree(&schema_path.0, &value, &mut obj);
+    // distinct child steps of each path where the tree is cut off
+    let mut truncated : BTreeMap<&[Step],BTreeSet<&Step>> = BTreeMap::new();
+    for (schema_path,value) in &subtree_path_values {
+      match cutoff {
+        Some(cutoff) if schema_path.0.len() > cutoff => {
+          truncated
+            .entry(&schema_path.0[..cutoff])
+            .or_default()
+            .insert(&schema_path.0[cutoff]);
+        }
+        _ => LeafPaths::traverse_tree(&schema_path.0, value, &mut obj),
+      }
+    }
+
+    for (prefix,children) in truncated {
+      LeafPaths::traverse_tree(prefix, Collector::Truncated(children.len()), &mut obj);
     }
     obj
   }
 
   pub fn delete(&mut self, path: String) {
     let path: SchemaPath = path.into();
-    let _ = self.0.remove(&path);
+    let _ = self.remove(&path);
+  }
+
+  /// Delete path and everything below it.
+  pub fn deletetree(&mut self, path: String) {
+    let paths = self.subtree_range(path.into()).map(|(path,_)| path.clone()).collect::<Vec<_>>();
+    for path in paths {
+      self.remove(&path);
+    }
   }
 }
 
@@ -541,7 +1488,7 @@ mod t {
   fn listpaths() {
     let path = path_of_strs!["uno", "due", "tre"];
     let mut leaf_paths = LeafPaths::new();
-    leaf_paths.0.insert(path, Leaf::String("empty not empty".into()));
+    leaf_paths.paths.insert(path, Leaf::String("empty not empty".into()));
 
     assert_eq!(leaf_paths.listpaths(), vec!["uno/due/tre"]);
     assert_eq!(leaf_paths.get("uno/due/tre".into()),Some("empty not empty".into()));
@@ -550,7 +1497,7 @@ mod t {
   #[test]
   fn get() {
     let mut leaf_paths = LeafPaths::new();
-    leaf_paths.0 .insert(path_of_strs!["wut"], Leaf::String("empty not empty".into()));
+    leaf_paths.paths.insert(path_of_strs!["wut"], Leaf::String("empty not empty".into()));
 
     assert_eq!(leaf_paths.listpaths(), vec!["wut"]);
     assert_eq!(leaf_paths.get("wut".into()), Some("empty not empty".into()));
@@ -617,8 +1564,59 @@ mod t {
     let expected_value_one = Leaf::String("some value".to_string());
     let expected_value_two = Leaf::String("this".to_string());
 
-    assert_eq!( leaf_paths.0.get(&expected_path_one).unwrap(), &expected_value_one );
-    assert_eq!( leaf_paths.0.get(&expected_path_two).unwrap(), &expected_value_two );
+    assert_eq!( leaf_paths.paths.get(&expected_path_one).unwrap(), &expected_value_one );
+    assert_eq!( leaf_paths.paths.get(&expected_path_two).unwrap(), &expected_value_two );
+  }
+
+  #[test]
+  fn listentries() {
+    let json = r#"{
+      "top": "this",
+      "next": {
+        "inner": "some value",
+        "count": 5,
+        "flag": true,
+        "wut": null
+      }
+    }"#;
+
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("root".into(), json.into()).unwrap();
+
+    let entries = leaf_paths.listentries("root/next".into());
+    assert_eq!(entries, vec![
+      ("root/next/count".to_string(), Leaf::Number("5".into())),
+      ("root/next/flag".to_string(), Leaf::String("true".into())),
+      ("root/next/inner".to_string(), Leaf::String("some value".into())),
+      ("root/next/wut".to_string(), Leaf::Null),
+    ]);
+
+    assert_eq!(leaf_paths.listentries("".into()).len(), 5);
+    assert_eq!(leaf_paths.listentries("nope".into()), vec![]);
+  }
+
+  #[test]
+  fn typed_values() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.add("string".into(), "5".into());
+    leaf_paths.setvalue("number".into(), Leaf::Number("5".into())).unwrap();
+    leaf_paths.setvalue("boolean".into(), Leaf::Boolean(false)).unwrap();
+    leaf_paths.setvalue("null".into(), Leaf::Null).unwrap();
+
+    // get can't tell these apart
+    assert_eq!(leaf_paths.get("string".into()), leaf_paths.get("number".into()));
+    assert_eq!(leaf_paths.getvalue("string".into()), Some(Leaf::String("5".into())));
+    assert_eq!(leaf_paths.getvalue("number".into()), Some(Leaf::Number("5".into())));
+    assert_eq!(leaf_paths.getvalue("boolean".into()), Some(Leaf::Boolean(false)));
+    assert_eq!(leaf_paths.getvalue("null".into()), Some(Leaf::Null));
+    assert_eq!(leaf_paths.getvalue("nope".into()), None);
+
+    let json = leaf_paths.gettree("".into(), None).to_json();
+    assert_eq!(json, serde_json::json!({"string": "5", "number": 5, "boolean": false, "null": null}));
+
+    let err = leaf_paths.setvalue("number".into(), Leaf::Number("five".into())).unwrap_err();
+    assert_eq!(err.to_string(), "five is not a number");
+    assert_eq!(leaf_paths.getvalue("number".into()), Some(Leaf::Number("5".into())));
   }
 
   #[test]
@@ -633,7 +1631,7 @@ mod t {
     let expected_path_one = path_of_strs!["uno", "due", "tre"];
     let expected_value_one = Leaf::String("singular".to_string());
 
-    assert_eq!( leaf_paths.0.get(&expected_path_one).unwrap(), &expected_value_one );
+    assert_eq!( leaf_paths.paths.get(&expected_path_one).unwrap(), &expected_value_one );
   }
 
   #[test]
@@ -653,8 +1651,8 @@ mod t {
     let expected_path_two = path_of_strs!["uno", "due", "tre", "top"];
     let expected_value_two = Leaf::String("this".to_string());
 
-    assert_eq!( leaf_paths.0.get(&expected_path_one).unwrap(), &expected_value_one );
-    assert_eq!( leaf_paths.0.get(&expected_path_two).unwrap(), &expected_value_two );
+    assert_eq!( leaf_paths.paths.get(&expected_path_one).unwrap(), &expected_value_one );
+    assert_eq!( leaf_paths.paths.get(&expected_path_two).unwrap(), &expected_value_two );
   }
 
   #[test]
@@ -757,17 +1755,17 @@ mod t {
 
     let mut leaf_paths = LeafPaths::new();
     leaf_paths.addtree("root".into(), json.into()).unwrap();
-    let subtree = leaf_paths.gettree("root".into());
+    let subtree = leaf_paths.gettree("root".into(), None);
     let json : serde_json::Value = (&subtree).into();
     assert_eq!(json.to_string(), r#"{"root":{"next":{"inner":"some value"},"stuff":[9,8,7,6,5],"things":[{"name":"one"},{"name":"two"},{"name":"tre"}],"top":"this","wut":null}}"#);
 
-    let subtree = leaf_paths.gettree("root/things".into());
+    let subtree = leaf_paths.gettree("root/things".into(), None);
     assert_eq!(subtree.to_json(), serde_json::json!({"root":{"things":[{"name":"one"},{"name":"two"},{"name":"tre"}]}}));
 
-    let subtree = leaf_paths.gettree("root/things/1".into());
+    let subtree = leaf_paths.gettree("root/things/1".into(), None);
     assert_eq!(subtree.to_json(), serde_json::json!({"root":{"things":[{"name":"two"}]}}));
 
-    let subtree = leaf_paths.gettree("does/not/exist/5/really".into());
+    let subtree = leaf_paths.gettree("does/not/exist/5/really".into(), None);
     assert_eq!(subtree, Collector::Empty);
   }
 
@@ -792,17 +1790,17 @@ mod t {
     let mut leaf_paths = LeafPaths::new();
     leaf_paths.addtree("root".into(), json.into()).unwrap();
 
-    let subtree = leaf_paths.gettree("root/next".into());
+    let subtree = leaf_paths.gettree("root/next".into(), None);
     let expected = serde_json::json!({"root":{"next":[{"inner":"some value","third":"stone from the sun","tweede":"'n ander waarde"}]}});
     assert_eq!(subtree.to_json(), expected);
 
-    let subtree = leaf_paths.gettree("root/things".into());
+    let subtree = leaf_paths.gettree("root/things".into(), None);
     assert_eq!(subtree.to_json().to_string(), r#"{"root":{"things":[{"name":"one"},{"name":"two"},{"name":"tre"}]}}"#);
 
-    let subtree = leaf_paths.gettree("root/things/1".into());
+    let subtree = leaf_paths.gettree("root/things/1".into(), None);
     assert_eq!(subtree.to_json().to_string(), r#"{"root":{"things":[{"name":"two"}]}}"#);
 
-    let subtree = leaf_paths.gettree("does/not/exist/5/really".into());
+    let subtree = leaf_paths.gettree("does/not/exist/5/really".into(), None);
     assert_eq!(subtree.to_json(), serde_json::Value::Null);
   }
 
@@ -813,7 +1811,7 @@ mod t {
     let mut leaf_paths = LeafPaths::new();
     leaf_paths.addtree("root".into(), sample_json_str.into()).unwrap();
 
-    let subtree = leaf_paths.gettree("root/web-app/servlet/2".into());
+    let subtree = leaf_paths.gettree("root/web-app/servlet/2".into(), None);
     let expected = serde_json::json!({
       "root": {
         "web-app": {
@@ -828,4 +1826,482 @@ mod t {
     });
     assert_eq!(subtree.to_json(), expected);
   }
+
+  #[test]
+  fn gettree_maxdepth() {
+    let sample_json_str = include_str!("../sample.json");
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("root".into(), sample_json_str.into()).unwrap();
+
+    let subtree = leaf_paths.gettree("root/web-app".into(), Some(1));
+    let expected = serde_json::json!({
+      "root": {
+        "web-app": {
+          "servlet": null,
+          "servlet-mapping": null,
+          "taglib": null,
+        }
+      }
+    });
+    assert_eq!(subtree.to_json(), expected);
+    let truncated = subtree.truncated().into_iter().map(|(path,n)| (path.to_string(), n)).collect::<Vec<_>>();
+    assert_eq!(truncated, vec![
+      ("root/web-app/servlet".to_string(), 5),
+      ("root/web-app/servlet-mapping".to_string(), 5),
+      ("root/web-app/taglib".to_string(), 2),
+    ]);
+
+    // leaves above the cutoff come through as normal
+    let subtree = leaf_paths.gettree("root/web-app/servlet/2".into(), Some(1));
+    assert_eq!(subtree, leaf_paths.gettree("root/web-app/servlet/2".into(), None));
+
+    let subtree = leaf_paths.gettree("root/web-app/servlet/4".into(), Some(1));
+    let expected = serde_json::json!({
+      "root": {
+        "web-app": {
+          "servlet": [
+            {
+              "init-param": null,
+              "servlet-class": "org.cofax.cms.CofaxToolsServlet",
+              "servlet-name": "cofaxTools",
+            }
+          ]
+        }
+      }
+    });
+    assert_eq!(subtree.to_json(), expected);
+    assert_eq!(subtree.truncated(), vec![("root/web-app/servlet/4/init-param".into(), 13)]);
+  }
+
+  #[test]
+  fn exists_and_count() {
+    let sample_json_str = include_str!("../sample.json");
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), sample_json_str.into()).unwrap();
+
+    assert!(leaf_paths.exists("web-app/taglib".into()));
+    assert!(leaf_paths.exists("web-app/taglib/taglib-uri".into()));
+    assert!(leaf_paths.exists("".into()));
+    assert!(!leaf_paths.exists("web-app/tag".into()));
+    assert!(!leaf_paths.exists("web-app/taglib/taglib-uri/deeper".into()));
+    assert!(!leaf_paths.exists("zzz".into()));
+
+    assert_eq!(leaf_paths.count("".into()), 74);
+    assert_eq!(leaf_paths.count("web-app/servlet".into()), 67);
+    assert_eq!(leaf_paths.count("web-app/servlet/0/init-param".into()), 42);
+    assert_eq!(leaf_paths.count("web-app/taglib/taglib-uri".into()), 1);
+    assert_eq!(leaf_paths.count("web-app/servlet/5".into()), 0);
+
+    assert_eq!(LeafPaths::new().count("".into()), 0);
+    assert!(!LeafPaths::new().exists("".into()));
+  }
+
+  #[test]
+  fn find() {
+    let sample_json_str = include_str!("../sample.json");
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), sample_json_str.into()).unwrap();
+
+    let paths = leaf_paths.find(&Matcher::Exact("ksm@pobox.com".into()), None);
+    assert_eq!(paths, vec!["web-app/servlet/0/init-param/configGlossary:adminEmail"]);
+
+    let paths = leaf_paths.find(&Matcher::Prefix("/usr/local/tomcat/logs/".into()), None);
+    assert_eq!(paths, vec![
+      "web-app/servlet/0/init-param/dataStoreLogFile",
+      "web-app/servlet/4/init-param/dataLogLocation",
+      "web-app/servlet/4/init-param/logLocation",
+    ]);
+
+    let paths = leaf_paths.find(&Matcher::Substring("cofax.tld".into()), Some("web-app/taglib".into()));
+    assert_eq!(paths, vec!["web-app/taglib/taglib-location", "web-app/taglib/taglib-uri"]);
+
+    let paths = leaf_paths.find(&Matcher::Substring("cofax.tld".into()), Some("web-app/servlet".into()));
+    assert_eq!(paths, Vec::<String>::new());
+
+    // numbers and booleans match on their text
+    let matcher = Matcher::regex("^(true|500)$").unwrap();
+    let paths = leaf_paths.find(&matcher, Some("web-app/servlet/0".into()));
+    assert_eq!(paths, vec![
+      "web-app/servlet/0/init-param/maxUrlLength",
+      "web-app/servlet/0/init-param/useDataStore",
+    ]);
+
+    assert!(Matcher::regex("(unclosed").is_err());
+  }
+
+  #[test]
+  fn path_pattern() {
+    let pattern = PathPattern::from("web-app/servlet/*/init-param");
+    assert_eq!(pattern.to_string(), "web-app/servlet/*/init-param");
+    assert_eq!(pattern.prefix().to_string(), "web-app/servlet");
+
+    assert!(pattern.matches(&split_slash_path("web-app/servlet/0/init-param")));
+    assert!(pattern.matches(&split_slash_path("web-app/servlet/wut/init-param")));
+    assert!(!pattern.matches(&split_slash_path("web-app/servlet/0")));
+    assert!(!pattern.matches(&split_slash_path("web-app/servlet/0/init-param/log")));
+    assert!(!pattern.matches(&split_slash_path("web-app/servlets/0/init-param")));
+
+    let pattern = PathPattern::from("web-app/**/templatePath");
+    assert_eq!(pattern.to_string(), "web-app/**/templatePath");
+    assert_eq!(pattern.prefix().to_string(), "web-app");
+    assert!(pattern.matches(&split_slash_path("web-app/servlet/0/init-param/templatePath")));
+    assert!(pattern.matches(&split_slash_path("web-app/templatePath")));
+    assert!(!pattern.matches(&split_slash_path("web-app/servlet/0/init-param/templatePath/x")));
+
+    let pattern = PathPattern::from("**");
+    assert!(pattern.matches(&[]));
+    assert!(pattern.matches(&split_slash_path("uno/due/tre")));
+
+    assert!(PathPattern::from("").is_empty());
+    assert_eq!(PathPattern::from("*/x").prefix(), SchemaPath(vec![]));
+  }
+
+  #[test]
+  fn value_index() {
+    let json = r#"[
+      {"name": "one", "email": "one@example.com"},
+      {"name": "two", "email": "two@example.com"},
+      {"name": "tre", "email": "one@example.com"}
+    ]"#;
+
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("users".into(), json.into()).unwrap();
+    leaf_paths.addindex("email".into(), "users/*/email".into()).unwrap();
+
+    let lookup = |leaf_paths : &LeafPaths, value : &str| leaf_paths.lookup("email".into(), value.into()).unwrap();
+    assert_eq!(lookup(&leaf_paths, "one@example.com"), vec!["users/0", "users/2"]);
+    assert_eq!(lookup(&leaf_paths, "two@example.com"), vec!["users/1"]);
+    // names are not in the index
+    assert_eq!(lookup(&leaf_paths, "one"), Vec::<String>::new());
+
+    // overwrite moves the entry
+    leaf_paths.add("users/2/email".into(), "tre@example.com".into());
+    assert_eq!(lookup(&leaf_paths, "one@example.com"), vec!["users/0"]);
+    assert_eq!(lookup(&leaf_paths, "tre@example.com"), vec!["users/2"]);
+
+    // addtree after the index exists
+    leaf_paths.addtree("users/3".into(), r#"{"name": "vier", "email": "two@example.com"}"#.into()).unwrap();
+    assert_eq!(lookup(&leaf_paths, "two@example.com"), vec!["users/1", "users/3"]);
+
+    leaf_paths.delete("users/1/email".into());
+    assert_eq!(lookup(&leaf_paths, "two@example.com"), vec!["users/3"]);
+
+    leaf_paths.clear();
+    assert_eq!(lookup(&leaf_paths, "two@example.com"), Vec::<String>::new());
+    leaf_paths.add("users/9/email".into(), "two@example.com".into());
+    assert_eq!(lookup(&leaf_paths, "two@example.com"), vec!["users/9"]);
+
+    leaf_paths.dropindex("email".into());
+    let err = leaf_paths.lookup("email".into(), "two@example.com".into()).unwrap_err();
+    assert_eq!(err.to_string(), "no index named email");
+
+    assert!(leaf_paths.addindex("everything".into(), "".into()).is_err());
+  }
+
+  #[test]
+  fn value_index_siblings() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("users/0".into(), r#"{"home": "x@example.com", "work": "x@example.com"}"#.into()).unwrap();
+    leaf_paths.addindex("emails".into(), "users/*/*".into()).unwrap();
+
+    let lookup = |leaf_paths : &LeafPaths| leaf_paths.lookup("emails".into(), "x@example.com".into()).unwrap();
+    assert_eq!(lookup(&leaf_paths), vec!["users/0"]);
+    // work still has it
+    leaf_paths.delete("users/0/home".into());
+    assert_eq!(lookup(&leaf_paths), vec!["users/0"]);
+    leaf_paths.add("users/0/work".into(), "y@example.com".into());
+    assert_eq!(lookup(&leaf_paths), Vec::<String>::new());
+  }
+
+  #[test]
+  fn numrange() {
+    let sample_json_str = include_str!("../sample.json");
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), sample_json_str.into()).unwrap();
+    // looks like a number, but isn't one
+    leaf_paths.add("web-app/servlet/0/init-param/stringy".into(), "1000".into());
+
+    let over_100 = leaf_paths.numrange(Bound::Excluded(100.0), Bound::Unbounded, Some("web-app/**".into())).unwrap();
+    assert_eq!(over_100, vec![
+      "web-app/servlet/0/init-param/cachePackageTagsStore",
+      "web-app/servlet/0/init-param/cachePackageTagsTrack",
+      "web-app/servlet/0/init-param/cachePagesTrack",
+      "web-app/servlet/0/init-param/maxUrlLength",
+    ]);
+
+    // "100" < "60" as strings, so this would find nothing if compared lexically
+    let pattern = Some("web-app/servlet/*/init-param/*".into());
+    assert_eq!(leaf_paths.numrange(Bound::Included(60.0), Bound::Included(100.0), pattern).unwrap(), vec![
+      "web-app/servlet/0/init-param/cachePackageTagsRefresh",
+      "web-app/servlet/0/init-param/cachePagesStore",
+      "web-app/servlet/0/init-param/cacheTemplatesTrack",
+      "web-app/servlet/0/init-param/dataStoreConnUsageLimit",
+      "web-app/servlet/0/init-param/dataStoreMaxConns",
+    ]);
+
+    let small = leaf_paths.numrange(Bound::Included(1.0), Bound::Excluded(10.0), None).unwrap();
+    assert_eq!(small, vec![
+      "web-app/servlet/4/init-param/adminGroupID",
+      "web-app/servlet/4/init-param/dataLog",
+      "web-app/servlet/4/init-param/log",
+      "web-app/servlet/4/init-param/lookInContext",
+    ]);
+
+    // same answers from an index
+    leaf_paths.addnumindex("numbers".into(), "web-app/**".into()).unwrap();
+    let indexed = leaf_paths.numrange(Bound::Excluded(100.0), Bound::Unbounded, Some("web-app/**".into())).unwrap();
+    assert_eq!(indexed, over_100);
+
+    // and the index keeps up with changes
+    leaf_paths.addtree("web-app/servlet/4/init-param/log".into(), "101".into()).unwrap();
+    leaf_paths.delete("web-app/servlet/0/init-param/maxUrlLength".into());
+    let indexed = leaf_paths.numrange(Bound::Excluded(100.0), Bound::Unbounded, Some("web-app/**".into())).unwrap();
+    assert_eq!(indexed, vec![
+      "web-app/servlet/0/init-param/cachePackageTagsStore",
+      "web-app/servlet/0/init-param/cachePackageTagsTrack",
+      "web-app/servlet/0/init-param/cachePagesTrack",
+      "web-app/servlet/4/init-param/log",
+    ]);
+
+    // empty and backwards ranges are just empty
+    let pattern = Some("web-app/**".into());
+    assert_eq!(leaf_paths.numrange(Bound::Excluded(100.0), Bound::Excluded(100.0), pattern.clone()).unwrap(), Vec::<String>::new());
+    assert_eq!(leaf_paths.numrange(Bound::Included(100.0), Bound::Included(10.0), pattern.clone()).unwrap(), Vec::<String>::new());
+
+    // -0 is 0, and NaN isn't anywhere, with an index or without
+    leaf_paths.setvalue("web-app/negative-zero".into(), Leaf::Number("-0".into())).unwrap();
+    for pattern in [pattern.clone(), Some("web-app/*".into())] {
+      assert_eq!(leaf_paths.numrange(Bound::Included(0.0), Bound::Included(0.0), pattern.clone()).unwrap(), vec!["web-app/negative-zero"]);
+      assert_eq!(leaf_paths.numrange(Bound::Included(-0.0), Bound::Excluded(1e-300), pattern.clone()).unwrap(), vec!["web-app/negative-zero"]);
+      let err = leaf_paths.numrange(Bound::Included(f64::NAN), Bound::Unbounded, pattern).unwrap_err();
+      assert_eq!(err.to_string(), "a range can't have NaN as a bound");
+    }
+  }
+
+  #[test]
+  fn aggregate() {
+    let sample_json_str = include_str!("../sample.json");
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), sample_json_str.into()).unwrap();
+    leaf_paths.addtree("web-app/servlet/1/init-param/cacheTemplatesStore".into(), "12.25".into()).unwrap();
+    leaf_paths.addtree("web-app/servlet/2/init-param/cacheTemplatesStore".into(), "0.1".into()).unwrap();
+    // not a number, so doesn't count
+    leaf_paths.add("web-app/servlet/3/init-param/cacheTemplatesStore".into(), "1000".into());
+
+    let aggregate = |op| {
+      leaf_paths
+        .aggregate("web-app/servlet/*/init-param/cacheTemplatesStore".into(), op)
+        .unwrap()
+        .map(|v| v.to_string())
+    };
+    // 0.1 is not exact as a float, so this checks that decimals are used
+    assert_eq!(aggregate(Aggregation::Sum), Some("62.35".into()));
+    assert_eq!(aggregate(Aggregation::Min), Some("0.1".into()));
+    assert_eq!(aggregate(Aggregation::Max), Some("50".into()));
+    assert_eq!(aggregate(Aggregation::Count), Some("3".into()));
+    assert_eq!(aggregate(Aggregation::Avg), Some("20.783333333333333333333333333".into()));
+
+    let aggregate = |op| leaf_paths.aggregate("web-app/taglib/*".into(), op).unwrap();
+    assert_eq!(aggregate(Aggregation::Sum), Some(Decimal::ZERO));
+    assert_eq!(aggregate(Aggregation::Count), Some(Decimal::ZERO));
+    assert_eq!(aggregate(Aggregation::Min), None);
+    assert_eq!(aggregate(Aggregation::Avg), None);
+
+    let total = leaf_paths.aggregate("web-app/servlet/4/**".into(), Aggregation::Sum).unwrap();
+    assert_eq!(total, Some(Decimal::from(7)));
+
+    leaf_paths.addtree("huge".into(), "1e300".into()).unwrap();
+    let err = leaf_paths.aggregate("huge".into(), Aggregation::Max).unwrap_err();
+    assert!(err.to_string().starts_with("1e+300 is not an exact decimal"));
+  }
+
+  #[test]
+  fn flatten() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("root".into(), r#"{"b": [true, 5], "a": "x"}"#.into()).unwrap();
+    // addtree keeps booleans as strings
+    leaf_paths.setvalue("root/b/0".into(), Leaf::Boolean(true)).unwrap();
+    let tree = leaf_paths.gettree("root/b".into(), None);
+
+    let nodes = tree.flatten();
+    assert_eq!(nodes, vec![
+      FlatNode { parent: None, step: None, value: FlatValue::Object },
+      FlatNode { parent: Some(0), step: Some(Step::Key("root".into())), value: FlatValue::Object },
+      FlatNode { parent: Some(1), step: Some(Step::Key("b".into())), value: FlatValue::Array },
+      FlatNode { parent: Some(2), step: Some(Step::Index(0)), value: FlatValue::Bool(true) },
+      FlatNode { parent: Some(2), step: Some(Step::Index(1)), value: FlatValue::Number("5".into()) },
+    ]);
+    assert_eq!(Collector::unflatten(&nodes).unwrap(), tree);
+
+    // sparse arrays keep their indexes
+    let tree = leaf_paths.gettree("root/b/1".into(), None);
+    let nodes = tree.flatten();
+    assert_eq!(nodes[3], FlatNode { parent: Some(2), step: Some(Step::Index(1)), value: FlatValue::Number("5".into()) });
+    assert_eq!(Collector::unflatten(&nodes).unwrap(), tree);
+
+    assert_eq!(Collector::unflatten(&[]).unwrap(), Collector::Empty);
+    assert_eq!(Collector::Empty.flatten(), vec![FlatNode { parent: None, step: None, value: FlatValue::Null }]);
+  }
+
+  #[test]
+  fn unflatten_bad() {
+    let root = FlatNode { parent: None, step: None, value: FlatValue::Object };
+    let leaf = |parent, step, value| FlatNode { parent, step, value };
+
+    let err = Collector::unflatten(&[root.clone(), leaf(None, None, FlatValue::Null)]).unwrap_err();
+    assert_eq!(err.to_string(), "node 1: only the first node can be without a parent");
+
+    let err = Collector::unflatten(&[root.clone(), leaf(Some(1), Some(Step::Key("a".into())), FlatValue::Null)]).unwrap_err();
+    assert_eq!(err.to_string(), "node 1: parent must come before its children");
+
+    let err = Collector::unflatten(&[root.clone(), leaf(Some(0), Some(Step::Index(0)), FlatValue::Null)]).unwrap_err();
+    assert_eq!(err.to_string(), "node 1: child of an object needs a key");
+
+    let nodes = [
+      root.clone(),
+      leaf(Some(0), Some(Step::Key("a".into())), FlatValue::Null),
+      leaf(Some(1), Some(Step::Key("b".into())), FlatValue::Null),
+    ];
+    let err = Collector::unflatten(&nodes).unwrap_err();
+    assert_eq!(err.to_string(), "node 2: parent is not an array or an object");
+
+    let err = Collector::unflatten(&[root, leaf(Some(0), Some(Step::Key("a".into())), FlatValue::Number("five".into()))]).unwrap_err();
+    assert_eq!(err.to_string(), "node 1: five is not a number");
+  }
+
+  #[test]
+  fn puttree() {
+    let sample_json_str = include_str!("../sample.json");
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), sample_json_str.into()).unwrap();
+
+    // round trip the whole store through flattened nodes
+    let nodes = leaf_paths.gettree("".into(), None).flatten();
+    let mut copy = LeafPaths::new();
+    copy.puttree("".into(), Collector::unflatten(&nodes).unwrap()).unwrap();
+    assert_eq!(copy.paths, leaf_paths.paths);
+
+    // put a subtree somewhere else
+    let tree = Collector::from(&serde_json::json!({"uno": [1, {"due": null}]}));
+    copy.puttree("other".into(), tree).unwrap();
+    assert_eq!(copy.get("other/uno/0".into()), Some("1".into()));
+    assert_eq!(copy.get("other/uno/1/due".into()), Some("null".into()));
+
+    let truncated = leaf_paths.gettree("web-app".into(), Some(1));
+    let err = copy.puttree("nope".into(), truncated).unwrap_err();
+    assert_eq!(err.to_string(), "can't put a truncated subtree at nope");
+    assert!(!copy.exists("nope".into()));
+  }
+
+  #[test]
+  fn scan_cursor() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), r#"{"a": 1, "b": {"c": 2, "d": 3, "e": 4}, "f": 5}"#.into()).unwrap();
+
+    let paths = |page : Vec<(String,Leaf<String>)>| page.into_iter().map(|(path,_)| path).collect::<Vec<_>>();
+
+    let mut cursor = ScanCursor::new("b".into());
+    assert_eq!(cursor.next(&leaf_paths, 2), vec![
+      ("b/c".to_string(), Leaf::Number("2".into())),
+      ("b/d".to_string(), Leaf::Number("3".into())),
+    ]);
+    // changes between pages, before and after the cursor
+    leaf_paths.add("b/b".into(), "before".into());
+    leaf_paths.delete("b/e".into());
+    leaf_paths.add("b/z".into(), "after".into());
+    assert_eq!(paths(cursor.next(&leaf_paths, 2)), vec!["b/z"]);
+    assert_eq!(cursor.next(&leaf_paths, 2), vec![]);
+    assert_eq!(cursor.next(&leaf_paths, 2), vec![]);
+
+    let mut cursor = ScanCursor::new("".into());
+    assert_eq!(paths(cursor.next(&leaf_paths, 3)), vec!["a", "b/b", "b/c"]);
+    assert_eq!(paths(cursor.next(&leaf_paths, 3)), vec!["b/d", "b/z", "f"]);
+    assert_eq!(cursor.next(&leaf_paths, 3), vec![]);
+
+    // as many as there are, without trying to make room for n
+    let mut cursor = ScanCursor::new("".into());
+    assert_eq!(cursor.next(&leaf_paths, u32::MAX as usize).len(), 6);
+  }
+
+  #[test]
+  fn gettree_root() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("uno".into(), r#"{"due": "tre"}"#.into()).unwrap();
+    leaf_paths.addtree("quattro".into(), r#"[5,6]"#.into()).unwrap();
+
+    let subtree = leaf_paths.gettree("".into(), None);
+    assert_eq!(subtree.to_json(), serde_json::json!({"uno": {"due": "tre"}, "quattro": [5,6]}));
+
+    let subtree = leaf_paths.gettree("".into(), Some(0));
+    assert_eq!(subtree, Collector::Truncated(2));
+    assert_eq!(subtree.truncated(), vec![(SchemaPath::from(vec![]), 2)]);
+
+    // only things under the root, not a value at it
+    let err = leaf_paths.addtree("".into(), "5".into()).unwrap_err();
+    assert_eq!(err.to_string(), "the root can't have a value, only things under it");
+    assert!(leaf_paths.puttree("".into(), Collector::Null).is_err());
+    assert_eq!(leaf_paths.getvalue("".into()), None);
+  }
+
+  thread_local! {
+    // for LeafPaths::set_clock, which can only take a plain fn
+    static NOW : std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+  }
+
+  #[test]
+  fn history() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.set_clock(|| NOW.get());
+
+    NOW.set(100);
+    leaf_paths.add("templatePath".into(), "templates/".into());
+    NOW.set(200);
+    leaf_paths.addtree("".into(), r#"{"templatePath": "t/"}"#.into()).unwrap();
+    NOW.set(300);
+    leaf_paths.delete("templatePath".into());
+    NOW.set(400);
+    leaf_paths.add("templatePath".into(), "new/".into());
+    leaf_paths.add("other".into(), "x".into());
+    NOW.set(500);
+    leaf_paths.clear();
+
+    assert_eq!(leaf_paths.history("templatePath".into(), 3), vec![
+      (500, None),
+      (400, Some(Leaf::String("new/".into()))),
+      (300, None),
+    ]);
+    assert_eq!(leaf_paths.history("other".into(), 10).len(), 2);
+    assert_eq!(leaf_paths.history("nope".into(), 10), vec![]);
+
+    assert_eq!(leaf_paths.getat("templatePath".into(), 50).unwrap(), None);
+    assert_eq!(leaf_paths.getat("templatePath".into(), 150).unwrap(), Some(Leaf::String("templates/".into())));
+    assert_eq!(leaf_paths.getat("templatePath".into(), 250).unwrap(), Some(Leaf::String("t/".into())));
+    assert_eq!(leaf_paths.getat("templatePath".into(), 350).unwrap(), None);
+    assert_eq!(leaf_paths.getat("templatePath".into(), 450).unwrap(), Some(Leaf::String("new/".into())));
+    assert_eq!(leaf_paths.getat("templatePath".into(), 550).unwrap(), None);
+  }
+
+  #[test]
+  fn undo() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("config".into(), r#"{"templatePath": "templates/", "servlets": ["a", "b"]}"#.into()).unwrap();
+    let before = leaf_paths.gettree("".into(), None);
+
+    // fat-fingered addtree, over the top of what was there
+    leaf_paths.undoable(|db| db.addtree("config".into(), r#"{"templatePath": "oops", "extra": 1}"#.into())).unwrap();
+    leaf_paths.undoable(|db| db.deletetree("config/servlets".into()));
+    assert!(!leaf_paths.exists("config/servlets".into()));
+    leaf_paths.undoable(|db| db.clear());
+    // not undoable, so it stays
+    leaf_paths.add("outside".into(), "x".into());
+    assert_eq!(leaf_paths.undo_paths(1).count(), 2);
+
+    assert_eq!(leaf_paths.undo(1), 1);
+    assert_eq!(leaf_paths.get("config/templatePath".into()), Some("oops".into()));
+    assert_eq!(leaf_paths.undo(10), 2);
+    assert_eq!(leaf_paths.undo(10), 0);
+
+    leaf_paths.delete("outside".into());
+    assert_eq!(leaf_paths.gettree("".into(), None), before);
+  }
 }
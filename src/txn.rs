@@ -0,0 +1,196 @@
+// Transactions over a LeafPaths. Writes are kept in an overlay until commit,
+// and reads look in the overlay before the store, so a transaction always
+// sees its own writes.
+
+use std::collections::BTreeMap;
+
+use crate::tree::{DingString, Leaf, LeafPaths, SchemaPath};
+
+pub struct Txn {
+  // check at commit that nobody else has changed what this txn has seen
+  detect_conflicts: bool,
+  // revision of the store when this txn started, or last committed
+  began: u64,
+  // The value of each path the first time this txn read or wrote it, with
+  // None for nothing there. This is what conflicts are checked against.
+  seen: BTreeMap<SchemaPath,Option<Leaf<String>>>,
+  // None for a delete
+  writes: BTreeMap<SchemaPath,Option<Leaf<String>>>,
+}
+
+impl Txn {
+  pub fn begin(store: &LeafPaths, detect_conflicts: bool) -> Self {
+    Self {
+      detect_conflicts,
+      began: store.revision(),
+      seen: BTreeMap::new(),
+      writes: BTreeMap::new(),
+    }
+  }
+
+  fn see(&mut self, store: &LeafPaths, path: &SchemaPath) {
+    if !self.seen.contains_key(path) {
+      self.seen.insert(path.clone(), store.paths.get(path).cloned());
+    }
+  }
+
+  fn write(&mut self, store: &LeafPaths, path: SchemaPath, leaf: Option<Leaf<String>>) {
+    self.see(store, &path);
+    self.writes.insert(path, leaf);
+  }
+
+  pub fn get(&mut self, store: &LeafPaths, path: String) -> Option<String> {
+    let path: SchemaPath = path.into();
+    if let Some(written) = self.writes.get(&path) {
+      return written.as_ref().map(ToString::to_string)
+    }
+
+    self.see(store, &path);
+    store.paths.get(&path).map(ToString::to_string)
+  }
+
+  pub fn add(&mut self, store: &LeafPaths, path: String, leaf: String) {
+    self.write(store, path.into(), Some(Leaf::String(leaf)));
+  }
+
+  pub fn addtree(&mut self, store: &LeafPaths, path: String, json: String) -> Result<(), DingString> {
+    // Let addtree do the work of turning json into paths, without touching the store.
+    let mut staged = LeafPaths::new();
+    staged.addtree(path, json)?;
+    for (path,leaf) in staged.paths.iter() {
+      self.write(store, path.clone(), Some(leaf.clone()));
+    }
+    Ok(())
+  }
+
+  pub fn delete(&mut self, store: &LeafPaths, path: String) {
+    self.write(store, path.into(), None);
+  }
+
+  /// What commit will do, with None for a delete.
+  pub fn staged(&self) -> impl Iterator<Item=(SchemaPath,Option<Leaf<String>>)> + '_ {
+    self.writes.iter().map(|(path,leaf)| (path.clone(), leaf.clone()))
+  }
+
+  /// Paths that commit will change.
+  pub fn written(&self) -> impl Iterator<Item=&SchemaPath> {
+    self.writes.keys()
+  }
+
+  /// Apply every write in one go. With conflict detection, fails without
+  /// applying anything if a commit since this txn began has changed anything
+  /// this txn has read or written. Either way, the txn starts over afterwards.
+  pub fn commit(&mut self, store: &mut LeafPaths) -> Result<(), DingString> {
+    // if the store hasn't changed at all, there's no need to look
+    if self.detect_conflicts && store.revision() != self.began {
+      let conflicts = self.seen
+        .iter()
+        .filter(|(path,seen)| store.paths.get(path) != seen.as_ref())
+        .map(|(path,_)| path.to_string())
+        .collect::<Vec<_>>();
+
+      if !conflicts.is_empty() {
+        self.rollback(store);
+        return Err(format!("conflicting changes at {}", conflicts.join(", ")).into())
+      }
+    }
+
+    store.apply(std::mem::take(&mut self.writes));
+    self.rollback(store);
+    Ok(())
+  }
+
+  /// Throw away all writes, and start over.
+  pub fn rollback(&mut self, store: &LeafPaths) {
+    self.began = store.revision();
+    self.seen.clear();
+    self.writes.clear();
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn read_own_writes() {
+    let mut store = LeafPaths::new();
+    store.add("uno".into(), "one".into());
+    store.add("due".into(), "two".into());
+
+    let mut txn = Txn::begin(&store, false);
+    txn.add(&store, "uno".into(), "een".into());
+    txn.delete(&store, "due".into());
+    txn.addtree(&store, "tre".into(), r#"{"quattro": 4}"#.into()).unwrap();
+
+    assert_eq!(txn.get(&store, "uno".into()), Some("een".into()));
+    assert_eq!(txn.get(&store, "due".into()), None);
+    assert_eq!(txn.get(&store, "tre/quattro".into()), Some("4".into()));
+
+    // nothing has happened to the store yet
+    assert_eq!(store.get("uno".into()), Some("one".into()));
+    assert_eq!(store.get("due".into()), Some("two".into()));
+    assert!(!store.exists("tre".into()));
+
+    txn.commit(&mut store).unwrap();
+    assert_eq!(store.get("uno".into()), Some("een".into()));
+    assert_eq!(store.get("due".into()), None);
+    assert_eq!(store.getvalue("tre/quattro".into()), Some(Leaf::Number("4".into())));
+  }
+
+  #[test]
+  fn rollback() {
+    let mut store = LeafPaths::new();
+    store.add("uno".into(), "one".into());
+
+    let mut txn = Txn::begin(&store, true);
+    txn.add(&store, "uno".into(), "een".into());
+    txn.rollback(&store);
+    assert_eq!(txn.get(&store, "uno".into()), Some("one".into()));
+
+    txn.commit(&mut store).unwrap();
+    assert_eq!(store.get("uno".into()), Some("one".into()));
+  }
+
+  #[test]
+  fn conflicts() {
+    let mut store = LeafPaths::new();
+    store.add("counter".into(), "1".into());
+    store.add("other".into(), "x".into());
+
+    let mut txn = Txn::begin(&store, true);
+    let mut other = Txn::begin(&store, true);
+
+    // both read-modify-write the same path
+    assert_eq!(txn.get(&store, "counter".into()), Some("1".into()));
+    txn.add(&store, "counter".into(), "2".into());
+    assert_eq!(other.get(&store, "counter".into()), Some("1".into()));
+    other.add(&store, "counter".into(), "3".into());
+
+    txn.commit(&mut store).unwrap();
+    let err = other.commit(&mut store).unwrap_err();
+    assert_eq!(err.to_string(), "conflicting changes at counter");
+    assert_eq!(store.get("counter".into()), Some("2".into()));
+
+    // other started over, so now it can go ahead
+    assert_eq!(other.get(&store, "counter".into()), Some("2".into()));
+    other.add(&store, "counter".into(), "3".into());
+    other.commit(&mut store).unwrap();
+    assert_eq!(store.get("counter".into()), Some("3".into()));
+
+    // changes elsewhere are not conflicts
+    txn.add(&store, "counter".into(), "4".into());
+    store.add("other".into(), "y".into());
+    txn.commit(&mut store).unwrap();
+    assert_eq!(store.get("counter".into()), Some("4".into()));
+
+    // and without detection, last commit wins
+    let mut txn = Txn::begin(&store, false);
+    txn.add(&store, "counter".into(), "5".into());
+    store.add("counter".into(), "6".into());
+    txn.commit(&mut store).unwrap();
+    assert_eq!(store.get("counter".into()), Some("5".into()));
+  }
+}
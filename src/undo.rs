@@ -0,0 +1,112 @@
+// Undo for the last few changes. A step is everything that one call changed,
+// kept as what each path had before. So undoing a step is putting those back.
+
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::tree::{Leaf, SchemaPath};
+
+/// Steps kept for undo.
+pub const UNDO_LIMIT: usize = 64;
+
+// what each path had before the step, None for nothing
+type UndoStep = BTreeMap<SchemaPath,Option<Leaf<String>>>;
+
+pub struct UndoLog {
+  limit: usize,
+  // oldest first
+  steps: VecDeque<UndoStep>,
+  // the step being recorded, if there is one
+  pending: Option<UndoStep>,
+}
+
+impl Default for UndoLog {
+  fn default() -> Self {
+    Self::new(UNDO_LIMIT)
+  }
+}
+
+impl UndoLog {
+  pub fn new(limit: usize) -> Self {
+    Self { limit, steps: VecDeque::new(), pending: None }
+  }
+
+  /// Start recording a step. false if there already was one, which carries
+  /// on instead.
+  pub fn begin(&mut self) -> bool {
+    let started = self.pending.is_none();
+    self.pending.get_or_insert_with(BTreeMap::new);
+    started
+  }
+
+  /// path had prev before it changed. Only the first change to a path in a
+  /// step counts, and nothing is recorded outside a step.
+  pub fn record(&mut self, path: &SchemaPath, prev: Option<&Leaf<String>>) {
+    if let Some(step) = &mut self.pending {
+      step.entry(path.clone()).or_insert_with(|| prev.cloned());
+    }
+  }
+
+  /// Stop recording, and keep the step if anything changed.
+  pub fn finish(&mut self) {
+    let Some(step) = self.pending.take() else { return };
+    if step.is_empty() { return }
+    self.steps.push_back(step);
+    while self.steps.len() > self.limit {
+      self.steps.pop_front();
+    }
+  }
+
+  /// Paths that undoing the last n steps would change.
+  pub fn paths(&self, n: usize) -> impl Iterator<Item=&SchemaPath> {
+    self.steps.iter().rev().take(n).flat_map(|step| step.keys())
+  }
+
+  /// What undoing the last n steps would put back, in the order it would.
+  pub fn changes(&self, n: usize) -> impl Iterator<Item=(&SchemaPath,&Option<Leaf<String>>)> {
+    self.steps.iter().rev().take(n).flatten()
+  }
+
+  /// Remove the last n steps, newest first.
+  pub fn take(&mut self, n: usize) -> Vec<UndoStep> {
+    let n = n.min(self.steps.len());
+    self.steps.drain(self.steps.len() - n ..).rev().collect()
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn steps() {
+    let leaf = |v: &str| Leaf::String(v.to_string());
+    let mut log = UndoLog::new(2);
+
+    // outside a step
+    log.record(&"uno".into(), None);
+    log.finish();
+    assert_eq!(log.take(10).len(), 0);
+
+    for (i,v) in ["uno", "due", "tre"].into_iter().enumerate() {
+      assert!(log.begin());
+      assert!(!log.begin());
+      log.record(&"path".into(), Some(&leaf(v)));
+      log.record(&"path".into(), Some(&leaf("ignored")));
+      log.record(&format!("other{i}").as_str().into(), None);
+      log.finish();
+    }
+    // nothing changed, so no step
+    log.begin();
+    log.finish();
+
+    // uno fell off the front
+    assert_eq!(log.paths(10).map(ToString::to_string).collect::<Vec<_>>(), vec!["other2", "path", "other1", "path"]);
+    let steps = log.take(10);
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].get(&"path".into()), Some(&Some(leaf("tre"))));
+    assert_eq!(steps[1].get(&"path".into()), Some(&Some(leaf("due"))));
+    assert_eq!(log.take(10).len(), 0);
+  }
+}
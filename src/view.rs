@@ -0,0 +1,120 @@
+// Read views. A view sees the store as it was when it was opened, however
+// much changes afterwards, so a read that takes several calls is consistent.
+//
+// The paths are shared between the store and its views. The first write while
+// a view is open copies them, so the view keeps the old version, and the old
+// version goes away when the last view of it does.
+
+use std::ops::{Deref, DerefMut};
+use std::rc::Rc;
+
+use crate::diff::Difference;
+use crate::tree::{Collector, LeafPaths};
+
+/// Copy on write. Reading goes straight through, writing copies first if
+/// anything else is sharing.
+#[derive(Debug, PartialEq)]
+pub struct Versioned<T: Clone>(Rc<T>);
+
+impl<T: Clone> Versioned<T> {
+  pub fn new(t: T) -> Self {
+    Self(Rc::new(t))
+  }
+
+  /// Another reference to this version, which won't see any later writes.
+  pub fn share(&self) -> Self {
+    Self(Rc::clone(&self.0))
+  }
+}
+
+impl<T: Clone> Deref for Versioned<T> {
+  type Target = T;
+
+  fn deref(&self) -> &T {
+    &self.0
+  }
+}
+
+impl<T: Clone> DerefMut for Versioned<T> {
+  fn deref_mut(&mut self) -> &mut T {
+    Rc::make_mut(&mut self.0)
+  }
+}
+
+/// The paths of a LeafPaths at one revision. Made by LeafPaths::view.
+pub struct ReadView(LeafPaths);
+
+impl ReadView {
+  pub fn new(leaf_paths: LeafPaths) -> Self {
+    Self(leaf_paths)
+  }
+
+  pub fn revision(&self) -> u64 {
+    self.0.revision()
+  }
+
+  pub fn get(&self, path: String) -> Option<String> {
+    self.0.get(path)
+  }
+
+  pub fn gettree(&self, path: String, maxdepth: Option<usize>) -> Collector {
+    self.0.gettree(path, maxdepth)
+  }
+
+  pub fn listpaths(&self) -> Vec<String> {
+    self.0.listpaths()
+  }
+
+  /// What changed in the subtree at path between this view and later.
+  pub fn diff(&self, path: String, later: &LeafPaths) -> Vec<Difference> {
+    self.0.diff_with(path.clone(), later, path)
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn copy_on_write() {
+    let mut current = Versioned::new(vec![1]);
+    // nothing else has it, so no copy
+    current.push(2);
+    assert_eq!(Rc::strong_count(&current.0), 1);
+
+    let old = current.share();
+    current.push(3);
+    assert_eq!(*old, vec![1, 2]);
+    assert_eq!(*current, vec![1, 2, 3]);
+    // the write copied, so old is the only one left with its version
+    assert_eq!(Rc::strong_count(&old.0), 1);
+    assert_eq!(Rc::strong_count(&current.0), 1);
+
+    let view = current.share();
+    assert_eq!(Rc::strong_count(&current.0), 2);
+    drop(view);
+    assert_eq!(Rc::strong_count(&current.0), 1);
+  }
+
+  #[test]
+  fn view_is_consistent() {
+    let mut leaf_paths = LeafPaths::new();
+    leaf_paths.addtree("".into(), r#"{"uno": {"due": "tre"}, "quattro": 4}"#.into()).unwrap();
+
+    let view = leaf_paths.view();
+    leaf_paths.add("uno/due".into(), "changed".into());
+    leaf_paths.delete("quattro".into());
+    leaf_paths.add("cinque".into(), "new".into());
+
+    assert_eq!(view.revision() + 3, leaf_paths.revision());
+    assert_eq!(view.get("uno/due".into()), Some("tre".into()));
+    assert_eq!(view.listpaths(), vec!["quattro", "uno/due"]);
+    assert_eq!(view.gettree("".into(), None).to_json(), serde_json::json!({"uno": {"due": "tre"}, "quattro": 4}));
+    assert_eq!(leaf_paths.listpaths(), vec!["cinque", "uno/due"]);
+
+    // another view sees the latest
+    assert_eq!(leaf_paths.view().get("uno/due".into()), Some("changed".into()));
+  }
+}
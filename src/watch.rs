@@ -0,0 +1,122 @@
+// Watches, which wait until something changes under a prefix rather than
+// polling the feed. Each waiting watch has a golem promise, which the client
+// awaits through golem, since golem only runs one call at a time on a worker.
+// Whatever changes a store completes the promises of the watches it ends, and
+// each client then reads its changes from the feed.
+//
+// This only keeps track of who is waiting for what. Making and completing
+// promises is up to the caller, so this doesn't need golem.
+
+use crate::tree::{LeafPaths, SchemaPath};
+
+/// Watches kept waiting at once. Clients can go away without saying, so
+/// past this the oldest are ended, and have to watch again.
+pub const WATCH_LIMIT: usize = 1024;
+
+/// A golem promise id, as kept in a snapshot.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Promise {
+  pub component: (u64,u64),
+  pub worker: String,
+  pub oplog: u64,
+}
+
+struct Waiting<Id> {
+  store: String,
+  prefix: SchemaPath,
+  since: u64,
+  promise: Id,
+}
+
+pub struct Watchers<Id> {
+  limit: usize,
+  // oldest first
+  waiting: Vec<Waiting<Id>>,
+}
+
+impl<Id> Default for Watchers<Id> {
+  fn default() -> Self {
+    Self::new(WATCH_LIMIT)
+  }
+}
+
+impl<Id> Watchers<Id> {
+  pub fn new(limit: usize) -> Self {
+    Self { limit, waiting: vec![] }
+  }
+
+  /// promise is waiting for a change at or below prefix in store after
+  /// revision since. The promises to complete now, to keep within the limit.
+  pub fn add(&mut self, store: String, prefix: SchemaPath, since: u64, promise: Id) -> Vec<Id> {
+    self.waiting.push(Waiting { store, prefix, since, promise });
+    let over = self.waiting.len().saturating_sub(self.limit);
+    self.waiting.drain(..over).map(|waiting| waiting.promise).collect()
+  }
+
+  /// (prefix, since, promise) of each watch waiting on store, oldest first.
+  pub fn waiting_on<'a>(&'a self, store: &'a str) -> impl Iterator<Item=(&'a SchemaPath,u64,&'a Id)> {
+    self.waiting
+      .iter()
+      .filter(move |waiting| waiting.store == store)
+      .map(|waiting| (&waiting.prefix, waiting.since, &waiting.promise))
+  }
+
+  /// The promises to complete now that store is db, which no longer wait.
+  pub fn ready(&mut self, store: &str, db: &LeafPaths) -> Vec<Id> {
+    self.take(|waiting| {
+      // a failure ends the wait too, so the watch can report it
+      waiting.store == store && db.feed_log()
+        .after(waiting.since, db.revision(), &waiting.prefix, 1)
+        .map_or(true, |(changes,_)| !changes.is_empty())
+    })
+  }
+
+  /// The promises waiting on store, which is gone.
+  pub fn forget(&mut self, store: &str) -> Vec<Id> {
+    self.take(|waiting| waiting.store == store)
+  }
+
+  fn take(&mut self, done: impl Fn(&Waiting<Id>) -> bool) -> Vec<Id> {
+    let (finished,waiting) = std::mem::take(&mut self.waiting)
+      .into_iter()
+      .partition::<Vec<_>,_>(|waiting| done(waiting));
+    self.waiting = waiting;
+    finished.into_iter().map(|waiting| waiting.promise).collect()
+  }
+}
+
+#[cfg(test)]
+mod t {
+  use super::*;
+  #[allow(unused_imports)]
+  use pretty_assertions::{assert_eq, assert_ne};
+
+  #[test]
+  fn waking() {
+    let mut db = LeafPaths::new();
+    db.add("other".into(), "x".into());
+
+    let mut watchers = Watchers::new(3);
+    watchers.add("uno".into(), "app".into(), 1, 1);
+    watchers.add("uno".into(), "app/name".into(), 1, 2);
+    watchers.add("due".into(), "app".into(), 1, 3);
+    assert_eq!(watchers.ready("uno", &db), Vec::<u32>::new());
+    assert_eq!(watchers.waiting_on("uno").map(|(_,_,promise)| *promise).collect::<Vec<_>>(), vec![1, 2]);
+
+    db.add("app/hosts/0".into(), "a".into());
+    assert_eq!(watchers.ready("uno", &db), vec![1]);
+    assert_eq!(watchers.ready("uno", &db), Vec::<u32>::new());
+
+    // drop touches everything
+    db.clear();
+    assert_eq!(watchers.ready("uno", &db), vec![2]);
+    assert_eq!(watchers.forget("due"), vec![3]);
+    assert_eq!(watchers.forget("due"), Vec::<u32>::new());
+
+    // too many, so the oldest goes
+    for promise in 4..7 {
+      assert_eq!(watchers.add("uno".into(), "app".into(), db.revision(), promise), Vec::<u32>::new());
+    }
+    assert_eq!(watchers.add("uno".into(), "app".into(), db.revision(), 7), vec![4]);
+  }
+}